@@ -0,0 +1,300 @@
+//! Kinds of units, described in a RON file loaded through the asset server.
+//!
+//! ```ron
+//! {
+//!     "knight": (
+//!         name: "Knight",
+//!         spritesheet: Some("spritesheet/Soldier/Soldier 03-1.png"),
+//!         stats: (life: 3, armor: 1),
+//!     ),
+//!     "boss": (
+//!         name: "Boss",
+//!         spritesheet: Some("spritesheet/Boss/Boss 01.png"),
+//!         layout: (tile_size: (96.0, 96.0), scale: 1.0),
+//!         stats: (life: 20, damage: 3),
+//!         ai: Attacking,
+//!     ),
+//! }
+//! ```
+//!
+//! The archetypes of the file are added to the built-in `soldier` and `archer`, which they can
+//! replace. A sheet must have the 3 columns by 4 rows of [crate::anim::UnitState] animations.
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::scenario::*;
+use crate::unit::*;
+
+pub struct ArchetypePlugin;
+
+impl Plugin for ArchetypePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<ArchetypeFile>()
+            .init_asset_loader::<ArchetypeLoader>()
+            .init_resource::<Archetypes>()
+            .add_event::<RestartBattle>()
+            .add_startup_system(load_archetypes)
+            .add_system(update_archetypes.before(ScenarioSystem::Apply));
+    }
+}
+
+/// Name of an archetype in [Archetypes], also kept on the units to know what they are.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Component, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct ArchetypeId(pub String);
+
+impl ArchetypeId {
+    pub fn new(id: impl Into<String>) -> Self {
+        ArchetypeId(id.into())
+    }
+}
+
+impl Default for ArchetypeId {
+    fn default() -> Self {
+        Self::new(Archetypes::DEFAULT)
+    }
+}
+
+impl fmt::Display for ArchetypeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Archetype {
+    pub name: String,
+    /// Without a spritesheet, units wear the one of their faction.
+    #[serde(default)]
+    pub spritesheet: Option<String>,
+    #[serde(default)]
+    pub layout: AtlasLayout,
+    #[serde(default)]
+    pub stats: UnitStats,
+    /// AI given when the scenario doesn't choose one.
+    #[serde(default)]
+    pub ai: AiKind,
+}
+
+/// How the spritesheet is cut and drawn.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AtlasLayout {
+    pub tile_size: (f32, f32),
+    pub columns: usize,
+    pub rows: usize,
+    pub scale: f32,
+}
+
+impl Default for AtlasLayout {
+    fn default() -> Self {
+        Self {
+            tile_size: (32.0, 32.0),
+            columns: 3,
+            rows: 4,
+            scale: 3.0,
+        }
+    }
+}
+
+impl Archetype {
+    pub fn texture_atlas(&self, asset_server: &AssetServer, spritesheet: &str) -> TextureAtlas {
+        let path = self.spritesheet.as_deref().unwrap_or(spritesheet);
+        TextureAtlas::from_grid(
+            asset_server.load(path),
+            Vec2::new(self.layout.tile_size.0, self.layout.tile_size.1),
+            self.layout.columns,
+            self.layout.rows,
+        )
+    }
+}
+
+/// Every archetype the units can be spawned from.
+pub struct Archetypes {
+    archetypes: HashMap<ArchetypeId, Archetype>,
+    handle: Handle<ArchetypeFile>,
+    file_applied: bool,
+}
+
+impl Default for Archetypes {
+    fn default() -> Self {
+        let mut archetypes = HashMap::new();
+        archetypes.insert(
+            ArchetypeId::new(Self::DEFAULT),
+            Archetype {
+                name: "Soldier".to_string(),
+                spritesheet: None,
+                layout: AtlasLayout::default(),
+                stats: UnitStats::default(),
+                ai: AiKind::Attacking,
+            },
+        );
+        archetypes.insert(
+            ArchetypeId::new("archer"),
+            Archetype {
+                name: "Archer".to_string(),
+                spritesheet: None,
+                layout: AtlasLayout::default(),
+                stats: UnitStats::ranged(),
+                ai: AiKind::Attacking,
+            },
+        );
+        Archetypes {
+            archetypes,
+            handle: Default::default(),
+            file_applied: false,
+        }
+    }
+}
+
+impl Archetypes {
+    /// Used for the units without an archetype or with an unknown one.
+    pub const DEFAULT: &'static str = "soldier";
+    pub const FILE: &'static str = "units.archetypes.ron";
+
+    pub fn get(&self, id: &ArchetypeId) -> Option<&Archetype> {
+        self.archetypes.get(id)
+    }
+
+    /// The archetype `id`, or the default one when it doesn't exist.
+    pub fn get_or_default(&self, id: &ArchetypeId) -> &Archetype {
+        self.get(id)
+            .unwrap_or_else(|| &self.archetypes[&ArchetypeId::default()])
+    }
+
+    pub fn insert(&mut self, id: ArchetypeId, archetype: Archetype) {
+        self.archetypes.insert(id, archetype);
+    }
+
+    /// Whether the archetypes of the file are known, or will never be.
+    pub fn is_ready(&self, asset_server: &AssetServer) -> bool {
+        self.file_applied
+            || !matches!(
+                asset_server.get_load_state(&self.handle),
+                LoadState::Loading | LoadState::Loaded
+            )
+    }
+}
+
+#[derive(Debug, Deserialize, TypeUuid)]
+#[uuid = "c1f4a6d2-7e3b-4b59-a8d1-2f6e9b0c3d57"]
+#[serde(transparent)]
+pub struct ArchetypeFile(pub HashMap<ArchetypeId, Archetype>);
+
+impl ArchetypeFile {
+    pub fn from_bytes(file: &str, bytes: &[u8]) -> Result<ArchetypeFile, anyhow::Error> {
+        ron::de::from_bytes(bytes).map_err(|e| {
+            anyhow::anyhow!(
+                "{}:{}: {} (column {})",
+                file,
+                e.position.line,
+                e.code,
+                e.position.col
+            )
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct ArchetypeLoader;
+
+impl AssetLoader for ArchetypeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let file = load_context.path().display().to_string();
+            let archetypes = ArchetypeFile::from_bytes(&file, bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(archetypes));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["archetypes.ron"]
+    }
+}
+
+fn load_archetypes(asset_server: Res<AssetServer>, mut archetypes: ResMut<Archetypes>) {
+    archetypes.handle = asset_server.load(Archetypes::FILE);
+}
+
+/// Add the archetypes of the file once loaded. When it changes the battle restarts, so the
+/// units already there get the new values too.
+fn update_archetypes(
+    mut events: EventReader<AssetEvent<ArchetypeFile>>,
+    files: Res<Assets<ArchetypeFile>>,
+    mut archetypes: ResMut<Archetypes>,
+    mut restart: EventWriter<RestartBattle>,
+) {
+    for event in events.iter() {
+        let (handle, modified) = match event {
+            AssetEvent::Created { handle } => (handle, false),
+            AssetEvent::Modified { handle } => (handle, true),
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != archetypes.handle {
+            continue;
+        }
+        if let Some(file) = files.get(handle) {
+            for (id, archetype) in file.0.iter() {
+                archetypes.insert(id.clone(), archetype.clone());
+            }
+            archetypes.file_applied = true;
+            if modified {
+                restart.send(RestartBattle);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::damage::DamageType;
+
+    #[test]
+    fn shipped_archetypes_are_valid() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(Archetypes::FILE);
+        let bytes = std::fs::read(&path).unwrap();
+        let file = ArchetypeFile::from_bytes(&path.display().to_string(), &bytes).unwrap();
+        assert!(!file.0.is_empty());
+        for archetype in file.0.values() {
+            if let Some(spritesheet) = &archetype.spritesheet {
+                let sheet = path.parent().unwrap().join(spritesheet);
+                assert!(sheet.exists(), "Missing {}", sheet.display());
+            }
+        }
+    }
+
+    #[test]
+    fn file_values_default_to_a_soldier() {
+        let source = r#"{ "mage": (name: "Mage", stats: (damage_type: Magic, attack_range: 3)) }"#;
+        let file = ArchetypeFile::from_bytes("mage", source.as_bytes()).unwrap();
+        let mage = &file.0[&ArchetypeId::new("mage")];
+        assert_eq!(mage.stats.damage_type, DamageType::Magic);
+        assert!(mage.stats.is_ranged());
+        assert_eq!(mage.stats.life, UnitStats::default().life);
+        assert_eq!(mage.layout.columns, 3);
+        assert!(matches!(mage.ai, AiKind::Attacking));
+    }
+
+    #[test]
+    fn unknown_archetype_falls_back_to_the_default() {
+        let archetypes = Archetypes::default();
+        let archer = archetypes.get_or_default(&ArchetypeId::new("archer"));
+        assert!(archer.stats.is_ranged());
+        let unknown = archetypes.get_or_default(&ArchetypeId::new("dragon"));
+        assert_eq!(unknown.name, "Soldier");
+    }
+}
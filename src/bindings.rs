@@ -0,0 +1,437 @@
+//! Keys of the actions of the game, saved in `bindings.ron` next to the game.
+//!
+//! ```ron
+//! (keys: {Pause: Escape, Join: Return, GridDebug: G})
+//! ```
+//!
+//! The actions missing from the file keep their default key, a key given to two actions is
+//! refused. The controls screen of the main menu rebinds them: click an action, then press its
+//! new key, or Escape to keep the old one. A key already bound to another action or handed out by
+//! the [KeyboardCombinationInput] pool is refused.
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum_macros::EnumIter;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::button::ButtonSpawner;
+use crate::input::*;
+use crate::state::*;
+use crate::utils::IntoEnumIterator;
+
+pub const BINDINGS_PATH: &str = "bindings.ron";
+
+/// Loads the bindings and gives the controls screen, for the game with a window.
+pub struct BindingsPlugin;
+
+impl Plugin for BindingsPlugin {
+    fn build(&self, app: &mut App) {
+        let path = Path::new(BINDINGS_PATH);
+        let bindings = if path.exists() {
+            Bindings::load(path).unwrap_or_else(|err| {
+                warn!("Default key bindings used: {}", err);
+                Bindings::default()
+            })
+        } else {
+            Bindings::default()
+        };
+        app.insert_resource(KeyboardCombinationInput::without(&bindings.keys()))
+            .insert_resource(bindings)
+            .init_resource::<Rebinding>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                capture_rebind_key
+                    .after(InputSystem)
+                    .before(update_keyboard),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::Controls).with_system(spawn_controls_screen),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Controls)
+                    .with_system(rebind_buttons)
+                    .with_system(update_rebind_texts),
+            );
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, EnumIter,
+)]
+pub enum Action {
+    /// Pause the battle, or resume it.
+    Pause,
+    /// A new player joins with the keyboard.
+    Join,
+    GridDebug,
+    ReplayPause,
+    ReplayFaster,
+    ReplaySlower,
+    ReplayBackward,
+    ReplayForward,
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    /// Keep the selected units in the middle of the screen, or stop.
+    Follow,
+}
+
+impl Action {
+    fn default_key(&self) -> KeyCode {
+        match self {
+            Action::Pause => KeyCode::Escape,
+            Action::Join => KeyCode::Return,
+            Action::GridDebug => KeyCode::G,
+            Action::ReplayPause => KeyCode::Space,
+            Action::ReplayFaster => KeyCode::Up,
+            Action::ReplaySlower => KeyCode::Down,
+            Action::ReplayBackward => KeyCode::Left,
+            Action::ReplayForward => KeyCode::Right,
+            // The arrows are taken by the replays and the letters by the combinations
+            Action::PanUp => KeyCode::Numpad8,
+            Action::PanDown => KeyCode::Numpad2,
+            Action::PanLeft => KeyCode::Numpad4,
+            Action::PanRight => KeyCode::Numpad6,
+            Action::Follow => KeyCode::Tab,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Action::Pause => "Pause",
+            Action::Join => "Join",
+            Action::GridDebug => "Grid debug",
+            Action::ReplayPause => "Replay pause",
+            Action::ReplayFaster => "Replay faster",
+            Action::ReplaySlower => "Replay slower",
+            Action::ReplayBackward => "Seek backward",
+            Action::ReplayForward => "Seek forward",
+            Action::PanUp => "Pan up",
+            Action::PanDown => "Pan down",
+            Action::PanLeft => "Pan left",
+            Action::PanRight => "Pan right",
+            Action::Follow => "Follow selection",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Why a key can't be bound to an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindError {
+    /// Already the key of this action.
+    Taken(KeyCode, Action),
+    /// Handed out to the buttons and players.
+    Pool(KeyCode),
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindError::Taken(key, action) => write!(f, "{:?} is the key of {}", key, action),
+            BindError::Pool(key) => write!(f, "{:?} is given to the buttons and players", key),
+        }
+    }
+}
+
+/// Key of every action.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    keys: BTreeMap<Action, KeyCode>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings {
+            keys: Action::iter()
+                .map(|action| (action, action.default_key()))
+                .collect(),
+        }
+    }
+}
+
+impl Bindings {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Bindings::parse(&source).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let source =
+            ron::ser::to_string_pretty(self, Default::default()).map_err(|e| e.to_string())?;
+        fs::write(path, source).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(source: &str) -> Result<Self, String> {
+        let mut bindings: Bindings = ron::de::from_str(source).map_err(|e| e.to_string())?;
+        for action in Action::iter() {
+            bindings.keys.entry(action).or_insert(action.default_key());
+        }
+        let mut bound: Vec<(KeyCode, Action)> = Vec::new();
+        for (action, key) in bindings.keys.iter() {
+            if let Some((_, other)) = bound.iter().find(|(other_key, _)| other_key == key) {
+                return Err(BindError::Taken(*key, *other).to_string());
+            }
+            bound.push((*key, *action));
+        }
+        Ok(bindings)
+    }
+
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.keys
+            .get(&action)
+            .copied()
+            .unwrap_or(action.default_key())
+    }
+
+    /// Every key bound.
+    pub fn keys(&self) -> Vec<KeyCode> {
+        self.keys.values().copied().collect()
+    }
+
+    pub fn pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        input.pressed(self.key(action))
+    }
+
+    pub fn just_pressed(&self, input: &Input<KeyCode>, action: Action) -> bool {
+        input.just_pressed(self.key(action))
+    }
+
+    /// Like [Input::clear_just_pressed], on the key of the action.
+    pub fn clear_just_pressed(&self, input: &mut Input<KeyCode>, action: Action) -> bool {
+        input.clear_just_pressed(self.key(action))
+    }
+
+    /// Give the key to the action, if no one else uses it.
+    pub fn bind(
+        &mut self,
+        action: Action,
+        key: KeyCode,
+        pool: &KeyboardCombinationInput,
+    ) -> Result<(), BindError> {
+        if let Some((other, _)) = self
+            .keys
+            .iter()
+            .find(|(other, bound)| **other != action && **bound == key)
+        {
+            return Err(BindError::Taken(key, *other));
+        }
+        if pool.uses(key) {
+            return Err(BindError::Pool(key));
+        }
+        self.keys.insert(action, key);
+        Ok(())
+    }
+}
+
+/// Action of the controls screen waiting for its new key.
+#[derive(Default)]
+struct Rebinding {
+    waiting: Option<Action>,
+    message: String,
+}
+
+#[derive(Component)]
+struct RebindButton(Action);
+
+#[derive(Component)]
+struct RebindMessage;
+
+/// The key pressed goes to the action waiting, Escape cancels. It is cleared before the keyboard
+/// combinations and the other systems see it.
+fn capture_rebind_key(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut bindings: ResMut<Bindings>,
+    pool: Res<KeyboardCombinationInput>,
+) {
+    let action = match rebinding.waiting {
+        Some(action) => action,
+        None => return,
+    };
+    let key = match keys.get_just_pressed().next() {
+        Some(key) => *key,
+        None => return,
+    };
+    keys.clear_just_pressed(key);
+    rebinding.waiting = None;
+    if key == KeyCode::Escape {
+        rebinding.message = format!("{} keeps {:?}", action, bindings.key(action));
+        return;
+    }
+    rebinding.message = match bindings.bind(action, key, &pool) {
+        Ok(()) => match bindings.save(Path::new(BINDINGS_PATH)) {
+            Ok(()) => format!("{} on {:?}", action, key),
+            Err(err) => format!("Can't save: {}", err),
+        },
+        Err(err) => err.to_string(),
+    };
+}
+
+fn spawn_controls_screen(
+    mut commands: Commands,
+    spawner: Res<ButtonSpawner>,
+    bindings: Res<Bindings>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    *rebinding = Rebinding::default();
+    let mut buttons = Vec::new();
+    for action in Action::iter() {
+        let label = format!("{} ({:?})", action, bindings.key(action));
+        spawner.spawn_button(&mut commands, label, None, |c| {
+            c.insert(RebindButton(action));
+            buttons.push(c.id());
+        });
+    }
+    // Rows of buttons, they don't fit in a single column
+    let grid = commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(90.0), Val::Auto),
+                flex_wrap: FlexWrap::WrapReverse,
+                justify_content: JustifyContent::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .push_children(&buttons)
+        .id();
+    let message = commands
+        .spawn_bundle(spawner.text(String::new(), 30.0))
+        .insert(RebindMessage)
+        .id();
+    let content = vec![grid, message];
+    spawn_screen(
+        &mut commands,
+        &spawner,
+        "Controls".to_string(),
+        content,
+        &[("Back", MenuAction::MainMenu)],
+    );
+}
+
+fn rebind_buttons(
+    mut rebinding: ResMut<Rebinding>,
+    query: Query<(&RebindButton, &Interaction), Changed<Interaction>>,
+) {
+    for (button, interaction) in query.iter() {
+        if *interaction == Interaction::Clicked {
+            rebinding.waiting = Some(button.0);
+            rebinding.message = format!("Press the new key of {}, or Escape", button.0);
+        }
+    }
+}
+
+/// Keys of the buttons and the message, after a rebinding.
+fn update_rebind_texts(
+    bindings: Res<Bindings>,
+    rebinding: Res<Rebinding>,
+    mut buttons: Query<(&RebindButton, &Children, &mut CombinationInput)>,
+    mut texts: Query<&mut Text>,
+    messages: Query<Entity, With<RebindMessage>>,
+) {
+    if !bindings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+    for (button, children, mut comb) in buttons.iter_mut() {
+        let key = match rebinding.waiting {
+            Some(action) if action == button.0 => "...".to_string(),
+            _ => format!("{:?}", bindings.key(button.0)),
+        };
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(*child) {
+                text.sections[0].value = format!("{} ({})", button.0, key);
+                // The combination of the button is written again after the label
+                comb.set_changed();
+            }
+        }
+    }
+    for entity in messages.iter() {
+        if let Ok(mut text) = texts.get_mut(entity) {
+            text.sections[0].value = rebinding.message.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_actions_keep_their_default_key() {
+        let bindings = Bindings::parse("(keys: {Pause: P, GridDebug: F1})").unwrap();
+        assert_eq!(bindings.key(Action::Pause), KeyCode::P);
+        assert_eq!(bindings.key(Action::GridDebug), KeyCode::F1);
+        assert_eq!(bindings.key(Action::Join), KeyCode::Return);
+        assert_eq!(
+            Bindings::parse(&ron::ser::to_string(&bindings).unwrap()),
+            Ok(bindings)
+        );
+    }
+
+    #[test]
+    fn key_of_two_actions_is_refused() {
+        assert_eq!(
+            Bindings::parse("(keys: {Pause: P, GridDebug: P})"),
+            Err("P is the key of Pause".to_string())
+        );
+        // Join keeps Return
+        assert_eq!(
+            Bindings::parse("(keys: {Pause: Return})"),
+            Err("Return is the key of Pause".to_string())
+        );
+    }
+
+    #[test]
+    fn escape_cancels_the_rebinding() {
+        let mut world = World::new();
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::Escape);
+        world.insert_resource(keys);
+        world.insert_resource(Rebinding {
+            waiting: Some(Action::GridDebug),
+            message: String::new(),
+        });
+        world.insert_resource(Bindings::default());
+        world.insert_resource(KeyboardCombinationInput::default());
+        let mut system = IntoSystem::into_system(capture_rebind_key);
+        system.initialize(&mut world);
+        system.run((), &mut world);
+
+        assert_eq!(world.resource::<Rebinding>().waiting, None);
+        assert_eq!(
+            world.resource::<Bindings>().key(Action::GridDebug),
+            KeyCode::G
+        );
+        assert!(!world
+            .resource::<Input<KeyCode>>()
+            .just_pressed(KeyCode::Escape));
+    }
+
+    #[test]
+    fn keys_used_elsewhere_are_refused() {
+        let mut bindings = Bindings::default();
+        let pool = KeyboardCombinationInput::without(&bindings.keys());
+        assert!(!pool.uses(KeyCode::G));
+        assert_eq!(
+            bindings.bind(Action::Pause, KeyCode::Return, &pool),
+            Err(BindError::Taken(KeyCode::Return, Action::Join))
+        );
+        assert_eq!(
+            bindings.bind(Action::Pause, KeyCode::A, &pool),
+            Err(BindError::Pool(KeyCode::A))
+        );
+        assert_eq!(bindings.bind(Action::Pause, KeyCode::F2, &pool), Ok(()));
+        assert_eq!(bindings.bind(Action::Join, KeyCode::Escape, &pool), Ok(()));
+        assert_eq!(bindings.key(Action::Join), KeyCode::Escape);
+    }
+}
@@ -0,0 +1,339 @@
+//! Spawners standing on the grid as buildings.
+//!
+//! A building holds its cell for its faction and has the life of a unit, so enemies attack it
+//! like any other unit and it is destroyed along with its spawner when its life runs out. When
+//! hostile units surround it without any defender around for `capture_time` seconds, the
+//! building and its spawner change hands.
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::anim::*;
+use crate::faction::*;
+use crate::grid::*;
+use crate::sim::*;
+use crate::spawn::*;
+use crate::unit::*;
+use crate::utils::{Direction, *};
+
+pub struct BuildingPlugin;
+
+impl Plugin for BuildingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BuildingSprite>()
+            .add_system_to_stage(SimStage, capture_buildings)
+            .add_system(add_building_sprite)
+            .add_system(update_building_sprite);
+    }
+}
+
+/// Building of a spawner, as written in the scenario files.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BuildingDef {
+    pub life: i32,
+    /// Seconds the enemies must surround the building to take it.
+    pub capture_time: f32,
+}
+
+impl Default for BuildingDef {
+    fn default() -> Self {
+        BuildingDef {
+            life: 10,
+            capture_time: 5.0,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Building {
+    pub max_life: i32,
+    pub capture_time: f32,
+    /// Faction surrounding the building, and since when.
+    capture: Option<(Faction, f32)>,
+}
+
+/// Place a building holding its cell, the spawner is added on the entity returned.
+pub fn spawn_building<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    grid: &mut Grid,
+    x: i32,
+    y: i32,
+    faction: Faction,
+    def: &BuildingDef,
+) -> EntityCommands<'w, 's, 'a> {
+    grid.change_by_count(x, y, faction, 1);
+    let mut entity = commands.spawn();
+    entity
+        .insert(Building {
+            max_life: def.life,
+            capture_time: def.capture_time,
+            capture: None,
+        })
+        .insert(UnitForce { faction })
+        .insert(UnitInfo {
+            last_x: x,
+            last_y: y,
+            target_x: x,
+            target_y: y,
+            ..Default::default()
+        })
+        .insert(UnitStats {
+            life: def.life,
+            ..Default::default()
+        })
+        .insert(GridTransform {
+            x: x as f32,
+            y: y as f32,
+            update_scale: false,
+        })
+        .insert(Transform::default());
+    entity
+}
+
+/// Hostile faction holding every walkable cell around the building with no defender, if there is
+/// one.
+fn surrounding_enemy(
+    grid: &Grid,
+    alliances: &Alliances,
+    x: i32,
+    y: i32,
+    owner: Faction,
+) -> Option<Faction> {
+    // Units of the owner standing on the building defend it too
+    if grid.get_count(x, y).unwrap_or(0) > 1 {
+        return None;
+    }
+    let mut enemy = None;
+    for d in Direction::iter() {
+        let (nx, ny) = (x + d.x(), y + d.y());
+        if !grid.is_walkable(nx, ny) {
+            continue;
+        }
+        // The defenders can still come back through a free cell
+        let other = grid.get_faction(nx, ny)?;
+        if alliances.are_allies(owner, other) {
+            return None;
+        }
+        match enemy {
+            None => enemy = Some(other),
+            // Two enemies fighting each other around the building, neither takes it
+            Some(first) if !alliances.are_allies(first, other) => return None,
+            Some(_) => {}
+        }
+    }
+    enemy
+}
+
+fn capture_buildings(
+    clock: Res<SimClock>,
+    mut grid: ResMut<Grid>,
+    alliances: Res<Alliances>,
+    mut buildings: Query<(
+        &mut Building,
+        &mut UnitForce,
+        &UnitInfo,
+        Option<&mut SpawnInfo>,
+    )>,
+) {
+    let now = clock.elapsed();
+    for (mut building, mut force, info, spawner) in buildings.iter_mut() {
+        let (x, y) = (info.last_x, info.last_y);
+        let enemy = match surrounding_enemy(&grid, &alliances, x, y, force.faction) {
+            Some(enemy) => enemy,
+            None => {
+                building.capture = None;
+                continue;
+            }
+        };
+        let since = match building.capture {
+            Some((faction, since)) if faction == enemy => since,
+            _ => {
+                building.capture = Some((enemy, now));
+                now
+            }
+        };
+        if now - since < building.capture_time {
+            continue;
+        }
+
+        info!(
+            "Faction {} captured the building of faction {} at ({}, {})",
+            enemy.0, force.faction.0, x, y
+        );
+        grid.change_by_count(x, y, force.faction, -1);
+        grid.change_by_count(x, y, enemy, 1);
+        force.faction = enemy;
+        if let Some(mut spawner) = spawner {
+            spawner.faction = enemy;
+        }
+        building.capture = None;
+    }
+}
+
+struct BuildingSprite {
+    handle: Handle<TextureAtlas>,
+}
+
+impl FromWorld for BuildingSprite {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        let texture = asset_server.load("spritesheet/effects/TornadoLoop_96x96.png");
+        let mut texture_atlases = world.get_resource_mut::<Assets<TextureAtlas>>().unwrap();
+        let atlas = TextureAtlas::from_grid(texture, Vec2::new(96.0, 96.0), 60, 1);
+        Self {
+            handle: texture_atlases.add(atlas),
+        }
+    }
+}
+
+fn add_building_sprite(
+    mut commands: Commands,
+    sprite: Res<BuildingSprite>,
+    query: Query<(Entity, &Transform), Added<Building>>,
+) {
+    for (entity, transform) in query.iter() {
+        commands
+            .entity(entity)
+            .insert_bundle(SpriteSheetBundle {
+                texture_atlas: sprite.handle.clone(),
+                transform: *transform,
+                ..Default::default()
+            })
+            .insert(Animation::new(AnimationMode::Loop, (0..60).collect()))
+            .insert(AnimTimer::new(0.05));
+    }
+}
+
+/// The building takes the color of its faction and fades as it loses life.
+fn update_building_sprite(
+    mut query: Query<
+        (&Building, &UnitForce, &UnitStats, &mut TextureAtlasSprite),
+        Or<(
+            Changed<UnitForce>,
+            Changed<UnitStats>,
+            Added<TextureAtlasSprite>,
+        )>,
+    >,
+) {
+    for (building, force, stats, mut sprite) in query.iter_mut() {
+        let life = stats.life as f32 / building.max_life.max(1) as f32;
+        sprite.color = force.faction.color();
+        sprite.color.set_a(0.4 + 0.6 * life.clamp(0.0, 1.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archetype::*;
+    use crate::camera::init_cameras_2d;
+    use crate::fx::*;
+    use crate::template::*;
+    use crate::utils::tests::*;
+
+    #[test]
+    fn surrounded_building_is_taken_by_the_enemy_alone() {
+        let alliances = Alliances::from_teams(&[0, 1, 1, 2]);
+        let mut grid = Grid::new(3, 3);
+        grid.add_unit(1, 1, Faction(0));
+        assert_eq!(surrounding_enemy(&grid, &alliances, 1, 1, Faction(0)), None);
+
+        grid.add_unit(0, 1, Faction(1));
+        grid.add_unit(1, 0, Faction(1));
+        grid.add_unit(2, 1, Faction(2));
+        grid.add_unit(1, 2, Faction(1));
+        assert!(matches!(
+            surrounding_enemy(&grid, &alliances, 1, 1, Faction(0)),
+            Some(Faction(1)) | Some(Faction(2))
+        ));
+
+        let mut grid = Grid::new(3, 3);
+        grid.add_unit(1, 1, Faction(0));
+        grid.add_unit(0, 1, Faction(1));
+        grid.add_unit(1, 0, Faction(1));
+        grid.add_unit(2, 1, Faction(1));
+        grid.add_unit(1, 2, Faction(3));
+        assert_eq!(surrounding_enemy(&grid, &alliances, 1, 1, Faction(0)), None);
+    }
+
+    #[test]
+    fn single_enemy_does_not_surround_a_building() {
+        let alliances = Alliances::from_teams(&[0, 1]);
+        let mut grid = Grid::new(3, 3);
+        grid.add_unit(1, 1, Faction(0));
+        grid.add_unit(0, 1, Faction(1));
+        assert_eq!(surrounding_enemy(&grid, &alliances, 1, 1, Faction(0)), None);
+
+        // Walls around need no one to hold them
+        grid.set_terrain(1, 0, Terrain::Wall);
+        grid.set_terrain(2, 1, Terrain::Wall);
+        grid.set_terrain(1, 2, Terrain::Wall);
+        assert_eq!(
+            surrounding_enemy(&grid, &alliances, 1, 1, Faction(0)),
+            Some(Faction(1))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn captured_spawner_changes_hands() {
+        fn init(
+            mut commands: Commands,
+            asset_server: Res<AssetServer>,
+            mut grid: ResMut<Grid>,
+            mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
+        ) {
+            let def = BuildingDef {
+                life: 5,
+                capture_time: 0.5,
+            };
+            spawn_building(&mut commands, &mut grid, 1, 0, Faction(0), &def).insert(SpawnInfo {
+                target_unit_count: Some(0),
+                spawn_delay: None,
+                last_spawn: 0.0,
+                faction: Faction(0),
+                x: 1,
+                y: 0,
+                template: UnitTemplate::default(),
+            });
+            spawn_unit(
+                &mut commands,
+                &asset_server,
+                &mut grid,
+                &mut texture_atlases,
+                &archetypes,
+                &ArchetypeId::default(),
+                0,
+                0,
+                Faction(1),
+                |_| {},
+            );
+        }
+
+        fn check_owner(
+            mut flag: ResMut<TestCheck<bool>>,
+            grid: Res<Grid>,
+            spawners: Query<&SpawnInfo>,
+        ) {
+            **flag = grid.get_faction(1, 0) == Some(Faction(1))
+                && spawners.iter().all(|spawner| spawner.faction == Faction(1));
+        }
+
+        App::new()
+            .add_plugin(Test::Time(1.5))
+            .add_plugin(SimPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(UnitPlugin)
+            .add_plugin(SpawnPlugin)
+            .add_plugin(BuildingPlugin)
+            .add_system(init_cameras_2d)
+            .insert_resource(Grid::new(2, 1))
+            .insert_resource(TestCheck::new(false).is_true())
+            .add_startup_system(init)
+            .add_system(check_owner)
+            .run();
+    }
+}
@@ -10,7 +10,10 @@ impl Plugin for ButtonPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ButtonMaterials>()
             .init_resource::<ButtonSpawner>()
-            .add_system_to_stage(CoreStage::PreUpdate, press_button_from_input)
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                press_button_from_input.after(update_keyboard),
+            )
             .add_system(init_button_material_component)
             .add_system(action_button_on_press)
             .add_system(change_material_for_state_system)
@@ -150,13 +153,14 @@ fn change_text_for_button(
 }
 
 fn press_button_from_input(
-    input: Res<Input<KeyCode>>,
+    input: Res<Keyboard>,
+    pads: Res<Input<GamepadButton>>,
     mut query: Query<(&CombinationInput, &mut Interaction)>,
 ) {
     for (comb, mut interaction) in query.iter_mut() {
-        if input.pressed_t(&*comb) {
+        if input.pressed_t(comb) || pads.pressed_t(comb) {
             *interaction = Interaction::Clicked;
-        } else if input.just_released_t(&*comb) {
+        } else if input.just_released_t(comb) || pads.just_released_t(comb) {
             *interaction = Interaction::None;
         }
     }
@@ -204,21 +208,25 @@ impl ButtonSpawner {
         }
         entity.insert(CombinationInput::new(true));
         entity.with_children(|parent| {
-            parent.spawn_bundle(TextBundle {
-                text: Text::from_section(
-                    text,
-                    TextStyle {
-                        font: self.font.clone(),
-                        font_size: 40.0,
-                        color: Color::rgb(0.9, 0.9, 0.9),
-                        ..Default::default()
-                    },
-                ),
-                ..Default::default()
-            });
+            parent.spawn_bundle(self.text(text, 40.0));
         });
         with_button(&mut entity);
     }
+
+    /// Text written like the buttons.
+    pub fn text(&self, text: String, font_size: f32) -> TextBundle {
+        TextBundle {
+            text: Text::from_section(
+                text,
+                TextStyle {
+                    font: self.font.clone(),
+                    font_size,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                },
+            ),
+            ..Default::default()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1,12 +1,69 @@
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
 use bevy::prelude::*;
+use bevy::render::camera::Viewport;
+use bevy::window::WindowResized;
+
+use crate::bindings::*;
+use crate::building::Building;
+use crate::grid::GridRenderDebug;
+use crate::picking::CursorCell;
+use crate::player::Players;
+use crate::selection::Selected;
+use crate::state::AppState;
+use crate::unit::UnitForce;
+
+/// Moves the main cameras over the battle: panning with the keys, the edges of the window or the
+/// left stick, zooming with the mouse wheel and following units. Every local player gets its
+/// own view, the window is split between them.
+pub struct CameraControlPlugin;
+
+impl Plugin for CameraControlPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(split_views)
+            // Keys and the cursor drive the menus outside of the battle
+            .add_system_set(SystemSet::on_update(AppState::Playing).with_system(pan_camera))
+            .add_system(zoom_camera)
+            .add_system(follow_units.after(pan_camera))
+            .add_system(clamp_camera.after(follow_units));
+    }
+}
 
 #[derive(Component)]
 pub struct MainCamera;
 #[derive(Component)]
 pub struct UICamera;
 
-pub fn init_cameras(commands: Commands) {
-    init_cameras_2d(commands);
+/// View of a local player, the first one also draws the UI.
+#[derive(Component)]
+pub struct PlayerView {
+    pub player: usize,
+    /// Keep the units of the player in the middle of the view, panning stops it.
+    pub follow: bool,
+}
+
+/// World units per second at the default zoom.
+const PAN_SPEED: f32 = 800.0;
+/// Pixels from the edges of the window where the cursor pans.
+const EDGE_PAN_MARGIN: f32 = 10.0;
+/// Part of the view gained or lost by each step of the wheel.
+const ZOOM_STEP: f32 = 0.1;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.0;
+// Touchpads scroll by pixels
+const PIXELS_PER_LINE: f32 = 100.0;
+/// How fast the camera catches up with the units followed, per second.
+const FOLLOW_SPEED: f32 = 5.0;
+
+pub fn init_cameras(mut commands: Commands) {
+    commands
+        .spawn_bundle(Camera2dBundle::default())
+        .insert(MainCamera)
+        .insert(UICamera)
+        .insert(PlayerView {
+            player: 0,
+            follow: false,
+        });
 }
 
 pub fn init_cameras_2d(mut commands: Commands) {
@@ -16,3 +73,268 @@ pub fn init_cameras_2d(mut commands: Commands) {
         .insert(MainCamera)
         .insert(UICamera);
 }
+
+/// Part of the window of a view when `views` players share it: side by side for two, then in a
+/// grid. The whole window for a single view.
+fn viewport(view: usize, views: usize, width: u32, height: u32) -> Option<Viewport> {
+    if views <= 1 {
+        return None;
+    }
+    let columns = if views == 2 {
+        2
+    } else {
+        (views as f32).sqrt().ceil() as usize
+    };
+    let rows = views.div_ceil(columns);
+    let size = UVec2::new(width / columns as u32, height / rows as u32);
+    let (column, row) = ((view % columns) as u32, (view / columns) as u32);
+    Some(Viewport {
+        physical_position: UVec2::new(column * size.x, row * size.y),
+        physical_size: size,
+        ..Default::default()
+    })
+}
+
+/// A camera per player, again when the window is resized.
+fn split_views(
+    mut commands: Commands,
+    players: Res<Players>,
+    windows: Res<Windows>,
+    mut resized: EventReader<WindowResized>,
+    mut views: Query<(Entity, &PlayerView, &mut Camera)>,
+) {
+    let resized = resized.iter().last().is_some();
+    if !players.is_changed() && !resized {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (width, height) = (window.physical_width(), window.physical_height());
+    let count = players.count().max(1);
+
+    for (entity, view, mut camera) in views.iter_mut() {
+        if view.player >= count {
+            commands.entity(entity).despawn();
+        } else {
+            camera.viewport = viewport(view.player, count, width, height);
+        }
+    }
+    for player in 0..count {
+        if views.iter().any(|(_, view, _)| view.player == player) {
+            continue;
+        }
+        // Drawn over the first view, which clears the window and draws the UI once
+        commands
+            .spawn_bundle(Camera2dBundle {
+                camera: Camera {
+                    viewport: viewport(player, count, width, height),
+                    priority: player as isize,
+                    ..Default::default()
+                },
+                camera_2d: Camera2d {
+                    clear_color: ClearColorConfig::None,
+                },
+                ..Default::default()
+            })
+            .insert(MainCamera)
+            .insert(UiCameraConfig { show_ui: false })
+            .insert(PlayerView {
+                player,
+                follow: true,
+            });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn pan_camera(
+    time: Res<Time>,
+    bindings: Res<Bindings>,
+    keys: Res<Input<KeyCode>>,
+    sticks: Res<Axis<GamepadAxis>>,
+    windows: Res<Windows>,
+    cursor: Res<CursorCell>,
+    players: Res<Players>,
+    mut cameras: Query<(
+        Entity,
+        &mut PlayerView,
+        &mut Transform,
+        &OrthographicProjection,
+    )>,
+) {
+    let mut keyboard = Vec2::ZERO;
+    for (action, towards) in [
+        (Action::PanUp, Vec2::Y),
+        (Action::PanDown, -Vec2::Y),
+        (Action::PanLeft, -Vec2::X),
+        (Action::PanRight, Vec2::X),
+    ] {
+        if bindings.pressed(&keys, action) {
+            keyboard += towards;
+        }
+    }
+    let mut edge = Vec2::ZERO;
+    if let (Some(window), Some(screen)) = (windows.get_primary(), cursor.screen) {
+        if screen.x < EDGE_PAN_MARGIN {
+            edge.x -= 1.0;
+        } else if screen.x > window.width() - EDGE_PAN_MARGIN {
+            edge.x += 1.0;
+        }
+        if screen.y < EDGE_PAN_MARGIN {
+            edge.y -= 1.0;
+        } else if screen.y > window.height() - EDGE_PAN_MARGIN {
+            edge.y += 1.0;
+        }
+    }
+    let keyboard_view = players.mouse_player().unwrap_or(0);
+
+    for (entity, mut view, mut transform, projection) in cameras.iter_mut() {
+        let mut direction = Vec2::ZERO;
+        if view.player == keyboard_view {
+            direction += keyboard;
+        }
+        if cursor.camera == Some(entity) {
+            direction += edge;
+        }
+        if let Some(gamepad) = players.get(view.player).and_then(|player| player.gamepad) {
+            let axis = |kind| sticks.get(GamepadAxis::new(gamepad, kind)).unwrap_or(0.0);
+            direction += Vec2::new(
+                axis(GamepadAxisType::LeftStickX),
+                axis(GamepadAxisType::LeftStickY),
+            );
+        }
+        if direction == Vec2::ZERO {
+            continue;
+        }
+        view.follow = false;
+        let step = direction.clamp_length_max(1.0) * PAN_SPEED * projection.scale;
+        transform.translation += (step * time.delta_seconds()).extend(0.0);
+    }
+}
+
+/// The view under the cursor zooms.
+fn zoom_camera(
+    cursor: Res<CursorCell>,
+    mut wheel: EventReader<MouseWheel>,
+    mut cameras: Query<&mut OrthographicProjection, With<MainCamera>>,
+) {
+    let scroll: f32 = wheel
+        .iter()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum();
+    if scroll == 0.0 {
+        return;
+    }
+    if let Some(mut projection) = cursor
+        .camera
+        .and_then(|camera| cameras.get_mut(camera).ok())
+    {
+        projection.scale = zoomed(projection.scale, scroll);
+    }
+}
+
+/// Scale of the projection after scrolling, scrolling up zooms in.
+fn zoomed(scale: f32, scroll: f32) -> f32 {
+    (scale * (1.0 - ZOOM_STEP).powf(scroll)).clamp(MIN_ZOOM, MAX_ZOOM)
+}
+
+/// The view of the mouse follows the selected units, the others follow all the units of their
+/// player. Follow is switched with its key, or North on a gamepad.
+#[allow(clippy::too_many_arguments)]
+fn follow_units(
+    time: Res<Time>,
+    bindings: Res<Bindings>,
+    keys: Res<Input<KeyCode>>,
+    pads: Res<Input<GamepadButton>>,
+    players: Res<Players>,
+    selected: Query<&Transform, (With<Selected>, Without<MainCamera>)>,
+    units: Query<(&Transform, &UnitForce), (Without<Building>, Without<MainCamera>)>,
+    mut cameras: Query<(&mut PlayerView, &mut Transform), With<MainCamera>>,
+) {
+    let mouse_view = players.mouse_player().unwrap_or(0);
+    for (mut view, mut transform) in cameras.iter_mut() {
+        let player = players.get(view.player);
+        let toggled = match player.and_then(|player| player.gamepad) {
+            Some(gamepad) => {
+                pads.just_pressed(GamepadButton::new(gamepad, GamepadButtonType::North))
+            }
+            None => view.player == mouse_view && bindings.just_pressed(&keys, Action::Follow),
+        };
+        if toggled {
+            view.follow = !view.follow;
+        }
+        if !view.follow {
+            continue;
+        }
+
+        let mut followed: Vec<Vec2> = Vec::new();
+        if view.player == mouse_view {
+            followed.extend(selected.iter().map(|unit| unit.translation.truncate()));
+        }
+        if followed.is_empty() {
+            if let Some(player) = player {
+                followed.extend(
+                    units
+                        .iter()
+                        .filter(|(_, force)| force.faction == player.faction)
+                        .map(|(unit, _)| unit.translation.truncate()),
+                );
+            }
+        }
+        if followed.is_empty() {
+            continue;
+        }
+        let goal = followed.iter().sum::<Vec2>() / followed.len() as f32;
+        let ratio = (FOLLOW_SPEED * time.delta_seconds()).min(1.0);
+        let position = transform.translation.truncate().lerp(goal, ratio);
+        transform.translation = position.extend(transform.translation.z);
+    }
+}
+
+/// The middle of every view stays over the grid.
+fn clamp_camera(
+    grid_debug: Res<GridRenderDebug>,
+    mut cameras: Query<&mut Transform, With<MainCamera>>,
+) {
+    let (min, max) = grid_debug.area();
+    for mut transform in cameras.iter_mut() {
+        let position = transform.translation.truncate();
+        let clamped = position.clamp(min, max);
+        if position != clamped {
+            transform.translation = clamped.extend(transform.translation.z);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zoom_is_clamped() {
+        assert!(zoomed(1.0, 1.0) < 1.0);
+        assert!(zoomed(1.0, -1.0) > 1.0);
+        assert_eq!(zoomed(1.0, 100.0), MIN_ZOOM);
+        assert_eq!(zoomed(1.0, -100.0), MAX_ZOOM);
+        assert_eq!(zoomed(2.0, 0.0), 2.0);
+    }
+
+    #[test]
+    fn window_is_split_between_the_views() {
+        let rect = |view, views| {
+            viewport(view, views, 800, 600)
+                .map(|viewport| (viewport.physical_position, viewport.physical_size))
+        };
+        assert!(rect(0, 1).is_none());
+        assert_eq!(rect(1, 2), Some((UVec2::new(400, 0), UVec2::new(400, 600))));
+        assert_eq!(rect(2, 3), Some((UVec2::new(0, 300), UVec2::new(400, 300))));
+        assert_eq!(
+            rect(4, 5),
+            Some((UVec2::new(266, 300), UVec2::new(266, 300)))
+        );
+    }
+}
@@ -0,0 +1,500 @@
+//! Orders given by the players to their force. They are applied at the start of a simulation
+//! step so a [crate::replay::Replay] can apply them again at the same tick.
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::archetype::ArchetypeId;
+use crate::building::Building;
+use crate::economy::*;
+use crate::faction::*;
+use crate::replay::ReplayMode;
+use crate::sim::*;
+use crate::spawn::SpawnInfo;
+use crate::unit::*;
+
+pub struct CommandPlugin;
+
+impl Plugin for CommandPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerCommands>()
+            .init_resource::<Treasury>()
+            .add_system_to_stage(SimStage, execute_player_commands);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PlayerCommand {
+    /// Every unit of the faction moving on its force goes to the cell and stays there.
+    Rally { faction: Faction, x: i32, y: i32 },
+    /// Pay a unit of the archetype, queued at the spawner of the faction on the cell.
+    Buy {
+        faction: Faction,
+        x: i32,
+        y: i32,
+        archetype: ArchetypeId,
+    },
+    /// Remove the last unit queued at the spawner on the cell, its gold goes back.
+    CancelPurchase { faction: Faction, x: i32, y: i32 },
+    /// Every spawner of the faction sends its next unit without waiting for its delay, the
+    /// ones sold buy the cheapest unit instead.
+    Spawn { faction: Faction },
+    /// The units of the faction moving on its force and at their rally point attack the nearest
+    /// enemies instead.
+    Charge { faction: Faction },
+    /// The units of the faction on the `units` cells attack an enemy on the cell, or walk to
+    /// it when there is none. Their AI takes over once done.
+    Order {
+        faction: Faction,
+        units: Vec<(i32, i32)>,
+        x: i32,
+        y: i32,
+    },
+}
+
+/// Cells from its rally point a unit can be to charge, so the units still on their way keep
+/// going.
+const CHARGE_RADIUS: i32 = 2;
+
+/// Commands waiting for the next simulation step.
+#[derive(Default)]
+pub struct PlayerCommands {
+    pending: Vec<PlayerCommand>,
+}
+
+impl PlayerCommands {
+    pub fn push(&mut self, command: PlayerCommand) {
+        self.pending.push(command);
+    }
+
+    fn take(&mut self) -> Vec<PlayerCommand> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn execute_player_commands(
+    mut commands: Commands,
+    clock: Res<SimClock>,
+    alliances: Res<Alliances>,
+    index: Res<UnitIndex>,
+    mut player_commands: ResMut<PlayerCommands>,
+    mut replay: ResMut<ReplayMode>,
+    mut treasury: ResMut<Treasury>,
+    mut move_on_force: Query<(Entity, &UnitForce, &UnitInfo, &mut MoveOnForceAI)>,
+    mut spawners: Query<(&mut SpawnInfo, Option<&mut SpawnQueue>)>,
+    forces: Query<(&UnitForce, Option<&Building>)>,
+) {
+    for command in replay.commands_for_tick(clock.tick(), player_commands.take()) {
+        info!("Player command: {:?}", command);
+        match command {
+            PlayerCommand::Rally { faction, x, y } => {
+                for (_, force, _, mut ai) in move_on_force.iter_mut() {
+                    if force.faction == faction {
+                        ai.target_x = x;
+                        ai.target_y = y;
+                        ai.stick_to_target = true;
+                    }
+                }
+            }
+            PlayerCommand::Buy {
+                faction,
+                x,
+                y,
+                archetype,
+            } => {
+                let bought = spawners
+                    .iter_mut()
+                    .find(|(spawner, _)| (spawner.faction, spawner.x, spawner.y) == (faction, x, y))
+                    .and_then(|(_, queue)| queue)
+                    .map(|mut queue| queue.buy(&mut treasury, archetype))
+                    .unwrap_or(false);
+                if !bought {
+                    info!("Faction {} can't buy at ({}, {})", faction.0, x, y);
+                }
+            }
+            PlayerCommand::CancelPurchase { faction, x, y } => {
+                if let Some((_, Some(mut queue))) = spawners
+                    .iter_mut()
+                    .find(|(spawner, _)| (spawner.faction, spawner.x, spawner.y) == (faction, x, y))
+                {
+                    queue.cancel(&mut treasury);
+                }
+            }
+            PlayerCommand::Spawn { faction } => {
+                let cheapest = treasury.catalog().into_iter().next();
+                let mut bought = false;
+                for (mut spawner, queue) in spawners.iter_mut() {
+                    if spawner.faction != faction {
+                        continue;
+                    }
+                    match (queue, &cheapest) {
+                        (Some(mut queue), Some((archetype, _))) if !bought => {
+                            bought = queue.buy(&mut treasury, archetype.clone());
+                        }
+                        (Some(_), _) => {}
+                        (None, _) => spawner.last_spawn = f32::MIN,
+                    }
+                }
+            }
+            PlayerCommand::Charge { faction } => {
+                for (entity, force, info, ai) in move_on_force.iter() {
+                    if force.faction == faction && at_rally_point(info.last_x, info.last_y, ai) {
+                        let mut unit = commands.entity(entity);
+                        unit.remove::<MoveOnForceAI>();
+                        AiKind::Attacking.insert(&mut unit);
+                    }
+                }
+            }
+            PlayerCommand::Order {
+                faction,
+                units,
+                x,
+                y,
+            } => {
+                let is_enemy = |unit: &&Entity| {
+                    forces
+                        .get(**unit)
+                        .map(|(force, _)| alliances.are_hostile(faction, force.faction))
+                        .unwrap_or(false)
+                };
+                let order = match index.units_at(x, y).iter().find(is_enemy) {
+                    Some(target) => UnitOrder::Attack(*target),
+                    None => UnitOrder::MoveTo { x, y },
+                };
+                for (unit_x, unit_y) in units {
+                    for unit in index.units_at(unit_x, unit_y) {
+                        if let Ok((force, None)) = forces.get(*unit) {
+                            if force.faction == faction {
+                                commands.entity(*unit).insert(order);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether a unit on the cell is close enough to the rally point of its AI to charge.
+fn at_rally_point(x: i32, y: i32, ai: &MoveOnForceAI) -> bool {
+    (x - ai.target_x).abs().max((y - ai.target_y).abs()) <= CHARGE_RADIUS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archetype::*;
+    use crate::camera::init_cameras_2d;
+    use crate::fx::*;
+    use crate::grid::*;
+    use crate::replay::*;
+    use crate::utils::tests::*;
+
+    #[test]
+    #[serial]
+    fn rally_changes_the_target_of_the_faction() {
+        fn init(
+            mut commands: Commands,
+            asset_server: Res<AssetServer>,
+            mut grid: ResMut<Grid>,
+            mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
+            mut player_commands: ResMut<PlayerCommands>,
+        ) {
+            for (x, faction) in [(0, Faction(0)), (1, Faction(1))] {
+                spawn_unit(
+                    &mut commands,
+                    &asset_server,
+                    &mut grid,
+                    &mut texture_atlases,
+                    &archetypes,
+                    &ArchetypeId::default(),
+                    x,
+                    0,
+                    faction,
+                    |c| {
+                        c.insert(MoveOnForceAI {
+                            target_x: x,
+                            target_y: 0,
+                            stick_to_target: true,
+                        });
+                    },
+                );
+            }
+            player_commands.push(PlayerCommand::Rally {
+                faction: Faction(0),
+                x: 3,
+                y: 3,
+            });
+        }
+
+        fn check_targets(
+            mut flag: ResMut<TestCheck<bool>>,
+            query: Query<(&UnitForce, &MoveOnForceAI)>,
+        ) {
+            **flag = query.iter().all(|(force, ai)| match force.faction {
+                Faction(0) => (ai.target_x, ai.target_y) == (3, 3),
+                _ => (ai.target_x, ai.target_y) == (1, 0),
+            });
+        }
+
+        App::new()
+            .add_plugin(Test::Time(0.5))
+            .add_plugin(SimPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(CommandPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(UnitPlugin)
+            .add_system(init_cameras_2d)
+            .insert_resource(Grid::new(4, 4))
+            .insert_resource(TestCheck::new(false).is_true())
+            .add_startup_system(init)
+            .add_system(check_targets)
+            .run();
+    }
+
+    #[test]
+    fn only_the_units_at_their_rally_point_charge() {
+        let ai = MoveOnForceAI {
+            target_x: 4,
+            target_y: 4,
+            stick_to_target: true,
+        };
+        assert!(at_rally_point(4, 4, &ai));
+        assert!(at_rally_point(2, 6, &ai));
+        assert!(!at_rally_point(1, 4, &ai));
+    }
+
+    #[test]
+    #[serial]
+    fn charge_makes_the_faction_attack() {
+        fn init(
+            mut commands: Commands,
+            asset_server: Res<AssetServer>,
+            mut grid: ResMut<Grid>,
+            mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
+            mut player_commands: ResMut<PlayerCommands>,
+        ) {
+            // The second unit of the faction is still far from its rally point
+            for (x, target_x, faction) in
+                [(0, 0, Faction(0)), (1, 5, Faction(0)), (5, 5, Faction(1))]
+            {
+                spawn_unit(
+                    &mut commands,
+                    &asset_server,
+                    &mut grid,
+                    &mut texture_atlases,
+                    &archetypes,
+                    &ArchetypeId::default(),
+                    x,
+                    0,
+                    faction,
+                    |c| {
+                        c.insert(MoveOnForceAI {
+                            target_x,
+                            target_y: 0,
+                            stick_to_target: true,
+                        });
+                    },
+                );
+            }
+            player_commands.push(PlayerCommand::Charge {
+                faction: Faction(0),
+            });
+        }
+
+        fn check_ai(
+            mut flag: ResMut<TestCheck<bool>>,
+            attacking: Query<&UnitForce, (With<AttackingAI>, Without<MoveOnForceAI>)>,
+            rallied: Query<&UnitForce, With<MoveOnForceAI>>,
+        ) {
+            **flag = attacking.iter().map(|force| force.faction).eq([Faction(0)])
+                && rallied
+                    .iter()
+                    .filter(|force| force.faction == Faction(0))
+                    .count()
+                    == 1;
+        }
+
+        App::new()
+            .add_plugin(Test::Time(0.5))
+            .add_plugin(SimPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(CommandPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(UnitPlugin)
+            .add_system(init_cameras_2d)
+            .insert_resource(Grid::new(6, 1))
+            .insert_resource(TestCheck::new(false).is_true())
+            .add_startup_system(init)
+            .add_system(check_ai)
+            .run();
+    }
+
+    #[test]
+    #[serial]
+    fn order_moves_the_units_then_gives_them_back_to_their_ai() {
+        fn init(
+            mut commands: Commands,
+            asset_server: Res<AssetServer>,
+            mut grid: ResMut<Grid>,
+            mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
+        ) {
+            spawn_unit(
+                &mut commands,
+                &asset_server,
+                &mut grid,
+                &mut texture_atlases,
+                &archetypes,
+                &ArchetypeId::default(),
+                0,
+                0,
+                Faction(0),
+                |c| {
+                    c.insert(MoveOnForceAI {
+                        target_x: 0,
+                        target_y: 0,
+                        stick_to_target: true,
+                    });
+                },
+            );
+        }
+
+        // Once the unit is in the index, so the command finds it on its cell
+        fn order(
+            mut sent: Local<bool>,
+            index: Res<UnitIndex>,
+            mut player_commands: ResMut<PlayerCommands>,
+        ) {
+            if !*sent && !index.units_at(0, 0).is_empty() {
+                player_commands.push(PlayerCommand::Order {
+                    faction: Faction(0),
+                    units: vec![(0, 0)],
+                    x: 3,
+                    y: 0,
+                });
+                *sent = true;
+            }
+        }
+
+        fn check_arrival(
+            mut flag: ResMut<TestCheck<bool>>,
+            query: Query<(&UnitInfo, Option<&UnitOrder>), With<MoveOnForceAI>>,
+        ) {
+            for (info, order) in query.iter() {
+                if (info.last_x, info.last_y) == (3, 0) && order.is_none() {
+                    **flag = true;
+                }
+            }
+        }
+
+        let mut clock = SimClock::one_step_per_frame();
+        clock.step = 0.1;
+        App::new()
+            .add_plugin(Test::Frames(100))
+            .add_plugin(SimPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(CommandPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(UnitPlugin)
+            .add_system(init_cameras_2d)
+            .insert_resource(clock)
+            .insert_resource(Grid::new(4, 1))
+            .insert_resource(TestCheck::new(false).is_true())
+            .add_startup_system(init)
+            .add_system(order)
+            .add_system(check_arrival)
+            .run();
+    }
+
+    #[test]
+    #[serial]
+    fn attack_out_of_reach_is_dropped() {
+        fn init(
+            mut commands: Commands,
+            asset_server: Res<AssetServer>,
+            mut grid: ResMut<Grid>,
+            mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
+        ) {
+            // The wall keeps the enemy out of reach
+            grid.set_terrain(2, 0, Terrain::Wall);
+            for (x, faction) in [(0, Faction(0)), (4, Faction(1))] {
+                spawn_unit(
+                    &mut commands,
+                    &asset_server,
+                    &mut grid,
+                    &mut texture_atlases,
+                    &archetypes,
+                    &ArchetypeId::default(),
+                    x,
+                    0,
+                    faction,
+                    |c| {
+                        if faction == Faction(0) {
+                            c.insert(MoveOnForceAI {
+                                target_x: 0,
+                                target_y: 0,
+                                stick_to_target: true,
+                            });
+                        }
+                    },
+                );
+            }
+        }
+
+        fn order(
+            mut sent: Local<bool>,
+            index: Res<UnitIndex>,
+            mut player_commands: ResMut<PlayerCommands>,
+        ) {
+            if !*sent && !index.units_at(4, 0).is_empty() {
+                player_commands.push(PlayerCommand::Order {
+                    faction: Faction(0),
+                    units: vec![(0, 0)],
+                    x: 4,
+                    y: 0,
+                });
+                *sent = true;
+            }
+        }
+
+        fn check_dropped(
+            mut ordered: Local<bool>,
+            mut flag: ResMut<TestCheck<bool>>,
+            query: Query<Option<&UnitOrder>, With<MoveOnForceAI>>,
+        ) {
+            for order in query.iter() {
+                match order {
+                    Some(UnitOrder::Attack(_)) => *ordered = true,
+                    None if *ordered => **flag = true,
+                    _ => {}
+                }
+            }
+        }
+
+        let mut clock = SimClock::one_step_per_frame();
+        clock.step = 0.1;
+        App::new()
+            .add_plugin(Test::Frames(100))
+            .add_plugin(SimPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(CommandPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(UnitPlugin)
+            .add_system(init_cameras_2d)
+            .insert_resource(clock)
+            .insert_resource(Grid::new(5, 1))
+            .insert_resource(TestCheck::new(false).is_true())
+            .add_startup_system(init)
+            .add_system(order)
+            .add_system(check_dropped)
+            .run();
+    }
+}
@@ -0,0 +1,152 @@
+//! Turn the damage sent by an attacker into the life lost by its target.
+//!
+//! A hit goes through, in order: the cover of the target cell, the evasion of the target, its
+//! resistance to the damage type, then its armor for physical damage.
+use bevy::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::unit::*;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum DamageType {
+    #[default]
+    Physical,
+    Fire,
+    Magic,
+}
+
+/// Part of the damage ignored for each type, negative values make the unit weak to it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Resistances {
+    pub physical: f32,
+    pub fire: f32,
+    pub magic: f32,
+}
+
+impl Resistances {
+    pub fn get(&self, kind: DamageType) -> f32 {
+        match kind {
+            DamageType::Physical => self.physical,
+            DamageType::Fire => self.fire,
+            DamageType::Magic => self.magic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitResult {
+    Hit,
+    Evaded,
+    BlockedByCover,
+}
+
+/// Sent for every resolved [DamageEvent], even when nothing got through.
+#[derive(Debug, Clone)]
+pub struct DamageApplied {
+    pub attacker: Entity,
+    pub target: Entity,
+    pub kind: DamageType,
+    /// Damage sent by the attacker.
+    pub raw: i32,
+    /// Life lost by the target.
+    pub amount: i32,
+    pub result: HitResult,
+}
+
+pub fn resolve_damage(
+    event: &DamageEvent,
+    target: &UnitStats,
+    cover: f32,
+    rng: &mut impl Rng,
+) -> DamageApplied {
+    let mut applied = DamageApplied {
+        attacker: event.attacker,
+        target: event.target,
+        kind: event.kind,
+        raw: event.amount,
+        amount: 0,
+        result: HitResult::Hit,
+    };
+    if rng.gen::<f32>() < cover {
+        applied.result = HitResult::BlockedByCover;
+        return applied;
+    }
+    if rng.gen::<f32>() < target.evasion {
+        applied.result = HitResult::Evaded;
+        return applied;
+    }
+
+    let resisted = event.amount as f32 * (1.0 - target.resistances.get(event.kind));
+    let mut amount = resisted.round().max(0.0) as i32;
+    if event.kind == DamageType::Physical && amount > 0 {
+        // Armor can't stop a hit completely
+        amount = (amount - target.armor).max(1);
+    }
+    applied.amount = amount;
+    applied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimRng;
+
+    fn hit(amount: i32, kind: DamageType) -> DamageEvent {
+        DamageEvent {
+            attacker: Entity::from_raw(0),
+            target: Entity::from_raw(1),
+            amount,
+            kind,
+        }
+    }
+
+    fn damage(event: DamageEvent, stats: &UnitStats) -> i32 {
+        resolve_damage(&event, stats, 0.0, &mut SimRng::new(0)).amount
+    }
+
+    #[test]
+    fn armor_only_reduces_physical_damage() {
+        let stats = UnitStats {
+            armor: 2,
+            ..Default::default()
+        };
+        assert_eq!(damage(hit(5, DamageType::Physical), &stats), 3);
+        assert_eq!(damage(hit(2, DamageType::Physical), &stats), 1);
+        assert_eq!(damage(hit(5, DamageType::Fire), &stats), 5);
+    }
+
+    #[test]
+    fn resistances_scale_damage() {
+        let stats = UnitStats {
+            resistances: Resistances {
+                fire: 0.5,
+                magic: -1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(damage(hit(4, DamageType::Fire), &stats), 2);
+        assert_eq!(damage(hit(4, DamageType::Magic), &stats), 8);
+        assert_eq!(damage(hit(4, DamageType::Physical), &stats), 4);
+    }
+
+    #[test]
+    fn evasion_and_cover_stop_the_hit() {
+        let mut rng = SimRng::new(0);
+        let stats = UnitStats {
+            evasion: 1.0,
+            ..Default::default()
+        };
+        let applied = resolve_damage(&hit(3, DamageType::Fire), &stats, 0.0, &mut rng);
+        assert_eq!((applied.result, applied.amount), (HitResult::Evaded, 0));
+
+        let stats = UnitStats::default();
+        let applied = resolve_damage(&hit(3, DamageType::Fire), &stats, 1.0, &mut rng);
+        assert_eq!(
+            (applied.result, applied.amount),
+            (HitResult::BlockedByCover, 0)
+        );
+    }
+}
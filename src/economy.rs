@@ -0,0 +1,491 @@
+//! Gold earned by the factions and spent to buy units at their spawners.
+//!
+//! ```ron
+//! economy: (start: 50, interval: 5.0, per_cell: 1, per_spawner: 5, prices: {"soldier": 20, "archer": 30}),
+//! ```
+//!
+//! Every `interval` seconds, a faction earns gold for each cell and each spawner it holds. A
+//! spawner marked `bought: true` in the scenario doesn't send units on its own anymore, it
+//! spawns the ones bought with [PlayerCommand::Buy] in order. A unit still in the queue gets its
+//! gold back to the buyer when cancelled, or when the spawner is destroyed or captured.
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::archetype::*;
+use crate::button::ButtonSpawner;
+use crate::command::*;
+use crate::faction::*;
+use crate::grid::*;
+use crate::scenario::*;
+use crate::sim::*;
+use crate::spawn::SpawnInfo;
+use crate::unit::{UnitStats, UnitSystem};
+
+pub struct EconomyPlugin;
+
+impl Plugin for EconomyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Treasury>()
+            .add_event::<BattleStarted>()
+            .add_system(start_economy.after(ScenarioSystem::Apply))
+            .add_system_to_stage(SimStage, collect_income)
+            .add_system_to_stage(SimStage, refund_lost_queues.after(UnitSystem::Damage));
+    }
+}
+
+/// Gold of the factions and a shop to buy units, for the game with a window.
+pub struct EconomyHudPlugin;
+
+impl Plugin for EconomyHudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(sync_shop)
+            .add_system(shop_actions)
+            .add_system(update_gold_text);
+    }
+}
+
+/// Economy of a scenario.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EconomyDef {
+    /// Gold of every faction when the battle starts.
+    pub start: u32,
+    /// Seconds between two incomes.
+    pub interval: f32,
+    pub per_cell: u32,
+    pub per_spawner: u32,
+    /// Archetypes sold and their price, the others can't be bought.
+    pub prices: HashMap<ArchetypeId, u32>,
+}
+
+impl Default for EconomyDef {
+    fn default() -> Self {
+        EconomyDef {
+            start: 50,
+            interval: 5.0,
+            per_cell: 1,
+            per_spawner: 5,
+            prices: [
+                (ArchetypeId::new("soldier"), 20),
+                (ArchetypeId::new("archer"), 30),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+}
+
+/// Gold of every faction.
+#[derive(Default)]
+pub struct Treasury {
+    rules: Option<EconomyDef>,
+    gold: [u32; Faction::MAX],
+    next_income: f32,
+}
+
+impl Treasury {
+    pub fn new(rules: Option<EconomyDef>) -> Self {
+        let (start, next_income) = match &rules {
+            Some(rules) => (rules.start, rules.interval),
+            None => (0, 0.0),
+        };
+        Treasury {
+            rules,
+            gold: [start; Faction::MAX],
+            next_income,
+        }
+    }
+
+    pub fn gold(&self, faction: Faction) -> u32 {
+        self.gold.get(faction.index()).cloned().unwrap_or(0)
+    }
+
+    pub fn price(&self, archetype: &ArchetypeId) -> Option<u32> {
+        self.rules
+            .as_ref()
+            .and_then(|rules| rules.prices.get(archetype).cloned())
+    }
+
+    /// Archetypes sold, the cheapest first.
+    pub fn catalog(&self) -> Vec<(ArchetypeId, u32)> {
+        let mut catalog: Vec<_> = match &self.rules {
+            Some(rules) => rules
+                .prices
+                .iter()
+                .map(|(id, price)| (id.clone(), *price))
+                .collect(),
+            None => Vec::new(),
+        };
+        catalog.sort_by(|(a, a_price), (b, b_price)| a_price.cmp(b_price).then(a.0.cmp(&b.0)));
+        catalog
+    }
+
+    /// Take the gold from the faction, nothing is taken when it doesn't have enough.
+    pub fn spend(&mut self, faction: Faction, amount: u32) -> bool {
+        match self.gold.get_mut(faction.index()) {
+            Some(gold) if *gold >= amount => {
+                *gold -= amount;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn earn(&mut self, faction: Faction, amount: u32) {
+        if let Some(gold) = self.gold.get_mut(faction.index()) {
+            *gold += amount;
+        }
+    }
+
+    /// Pay every income due at `now`, for the cells and spawners held by each faction.
+    pub fn collect(
+        &mut self,
+        now: f32,
+        cells: &[u32; Faction::MAX],
+        spawners: &[u32; Faction::MAX],
+    ) {
+        let (interval, per_cell, per_spawner) = match &self.rules {
+            Some(rules) if rules.interval > 0.0 => {
+                (rules.interval, rules.per_cell, rules.per_spawner)
+            }
+            _ => return,
+        };
+        while now >= self.next_income {
+            self.next_income += interval;
+            for faction in Faction::all() {
+                let i = faction.index();
+                self.earn(faction, cells[i] * per_cell + spawners[i] * per_spawner);
+            }
+        }
+    }
+}
+
+/// Units bought at a spawner and waiting to come out, with the gold paid for each.
+#[derive(Component)]
+pub struct SpawnQueue {
+    /// Faction which paid for the units, the spawner may have been captured since.
+    buyer: Faction,
+    units: VecDeque<(ArchetypeId, u32)>,
+}
+
+impl SpawnQueue {
+    pub const MAX: usize = 5;
+
+    pub fn new(buyer: Faction) -> Self {
+        SpawnQueue {
+            buyer,
+            units: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+
+    /// Pay the unit and queue it, when it is sold and the buyer has the gold.
+    pub fn buy(&mut self, treasury: &mut Treasury, archetype: ArchetypeId) -> bool {
+        if self.units.len() >= Self::MAX {
+            return false;
+        }
+        let price = match treasury.price(&archetype) {
+            Some(price) => price,
+            None => return false,
+        };
+        if !treasury.spend(self.buyer, price) {
+            return false;
+        }
+        self.units.push_back((archetype, price));
+        true
+    }
+
+    /// Remove the last unit queued and give its gold back.
+    pub fn cancel(&mut self, treasury: &mut Treasury) -> bool {
+        match self.units.pop_back() {
+            Some((_, paid)) => {
+                treasury.earn(self.buyer, paid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Next unit to spawn for the spawner of `faction`. None when the spawner isn't the
+    /// buyer's anymore, its queue waits to be refunded.
+    pub fn pop(&mut self, faction: Faction) -> Option<ArchetypeId> {
+        if faction != self.buyer {
+            return None;
+        }
+        self.units.pop_front().map(|(archetype, _)| archetype)
+    }
+}
+
+fn start_economy(mut events: EventReader<BattleStarted>, mut treasury: ResMut<Treasury>) {
+    for event in events.iter() {
+        *treasury = Treasury::new(event.economy.clone());
+    }
+}
+
+fn collect_income(
+    clock: Res<SimClock>,
+    grid: Res<Grid>,
+    mut treasury: ResMut<Treasury>,
+    spawners: Query<&SpawnInfo>,
+) {
+    let mut count = [0; Faction::MAX];
+    for spawner in spawners.iter() {
+        count[spawner.faction.index()] += 1;
+    }
+    treasury.collect(clock.elapsed(), &grid.cells_by_faction(), &count);
+}
+
+/// Queues of destroyed or captured spawners go back to their buyer.
+fn refund_lost_queues(
+    mut treasury: ResMut<Treasury>,
+    mut queues: Query<(&SpawnInfo, &mut SpawnQueue, Option<&UnitStats>)>,
+) {
+    for (spawner, mut queue, stats) in queues.iter_mut() {
+        let destroyed = stats.map(|stats| stats.life <= 0).unwrap_or(false);
+        if !destroyed && queue.buyer == spawner.faction {
+            continue;
+        }
+        while queue.cancel(&mut treasury) {}
+        queue.buyer = spawner.faction;
+    }
+}
+
+/// Row of the shop of a faction.
+#[derive(Component)]
+struct Shop;
+
+#[derive(Component)]
+struct GoldText(Faction);
+
+#[derive(Debug, Clone, Component)]
+enum ShopButton {
+    Buy(Faction, ArchetypeId),
+    Cancel(Faction),
+}
+
+/// A shop row for each faction buying its units, again when they or the prices change.
+fn sync_shop(
+    mut commands: Commands,
+    spawner: Res<ButtonSpawner>,
+    treasury: Res<Treasury>,
+    archetypes: Res<Archetypes>,
+    queues: Query<&SpawnInfo, With<SpawnQueue>>,
+    shops: Query<Entity, With<Shop>>,
+    mut shown: Local<(Vec<Faction>, Vec<(ArchetypeId, u32)>)>,
+) {
+    let mut buyers: Vec<Faction> = queues.iter().map(|spawner| spawner.faction).collect();
+    buyers.sort_by_key(|faction| faction.0);
+    buyers.dedup();
+    let catalog = treasury.catalog();
+    if shown.0 == buyers && shown.1 == catalog {
+        return;
+    }
+
+    for shop in shops.iter() {
+        commands.entity(shop).despawn_recursive();
+    }
+    for (row, faction) in buyers.iter().enumerate() {
+        let mut buttons = Vec::new();
+        for (archetype, price) in catalog.iter() {
+            let name = archetypes
+                .get(archetype)
+                .map(|archetype| archetype.name.clone())
+                .unwrap_or_else(|| archetype.to_string());
+            let action = ShopButton::Buy(*faction, archetype.clone());
+            spawner.spawn_button(&mut commands, format!("{} {}", name, price), None, |c| {
+                c.insert(action);
+                buttons.push(c.id());
+            });
+        }
+        spawner.spawn_button(&mut commands, "Cancel".to_string(), None, |c| {
+            c.insert(ShopButton::Cancel(*faction));
+            buttons.push(c.id());
+        });
+
+        let mut text = spawner.text(String::new(), 30.0);
+        text.text.sections[0].style.color = faction.color();
+        let text = commands.spawn_bundle(text).insert(GoldText(*faction)).id();
+        commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        bottom: Val::Px(10.0 + 75.0 * row as f32),
+                        left: Val::Px(10.0),
+                        ..Default::default()
+                    },
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                color: Color::NONE.into(),
+                ..Default::default()
+            })
+            .insert(Shop)
+            .push_children(&[text])
+            .push_children(&buttons);
+    }
+    *shown = (buyers, catalog);
+}
+
+/// Buy at the spawner with the shortest queue, cancel at the one with the longest.
+fn shop_actions(
+    mut player_commands: ResMut<PlayerCommands>,
+    query: Query<(&ShopButton, &Interaction), Changed<Interaction>>,
+    queues: Query<(&SpawnInfo, &SpawnQueue)>,
+) {
+    for (button, interaction) in query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let faction = match button {
+            ShopButton::Buy(faction, _) | ShopButton::Cancel(faction) => *faction,
+        };
+        let spawners = queues
+            .iter()
+            .filter(|(spawner, queue)| spawner.faction == faction && queue.buyer == faction);
+        let command = match button {
+            ShopButton::Buy(_, archetype) => {
+                spawners
+                    .min_by_key(|(_, queue)| queue.len())
+                    .map(|(spawner, _)| PlayerCommand::Buy {
+                        faction,
+                        x: spawner.x,
+                        y: spawner.y,
+                        archetype: archetype.clone(),
+                    })
+            }
+            ShopButton::Cancel(_) => spawners
+                .filter(|(_, queue)| !queue.is_empty())
+                .max_by_key(|(_, queue)| queue.len())
+                .map(|(spawner, _)| PlayerCommand::CancelPurchase {
+                    faction,
+                    x: spawner.x,
+                    y: spawner.y,
+                }),
+        };
+        if let Some(command) = command {
+            player_commands.push(command);
+        }
+    }
+}
+
+fn update_gold_text(treasury: Res<Treasury>, mut query: Query<(&GoldText, &mut Text)>) {
+    for (gold, mut text) in query.iter_mut() {
+        if treasury.is_changed() || text.sections[0].value.is_empty() {
+            text.sections[0].value =
+                format!("Faction {}: {} gold", gold.0 .0, treasury.gold(gold.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::init_cameras_2d;
+    use crate::fx::*;
+    use crate::replay::*;
+    use crate::spawn::*;
+    use crate::template::*;
+    use crate::unit::*;
+    use crate::utils::tests::*;
+
+    fn treasury() -> Treasury {
+        Treasury::new(Some(EconomyDef {
+            start: 50,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn cancelled_units_are_refunded() {
+        let mut treasury = treasury();
+        let mut queue = SpawnQueue::new(Faction(1));
+        assert!(queue.buy(&mut treasury, ArchetypeId::new("archer")));
+        assert!(!queue.buy(&mut treasury, ArchetypeId::new("archer")));
+        assert!(!queue.buy(&mut treasury, ArchetypeId::new("boss")));
+        assert!(queue.buy(&mut treasury, ArchetypeId::new("soldier")));
+        assert_eq!(treasury.gold(Faction(1)), 0);
+        assert_eq!(treasury.gold(Faction(0)), 50);
+
+        assert!(queue.cancel(&mut treasury));
+        assert_eq!(treasury.gold(Faction(1)), 20);
+        assert_eq!(queue.pop(Faction(0)), None);
+        assert_eq!(queue.pop(Faction(1)), Some(ArchetypeId::new("archer")));
+        assert!(!queue.cancel(&mut treasury));
+    }
+
+    #[test]
+    fn income_comes_from_cells_and_spawners() {
+        let mut treasury = treasury();
+        let mut cells = [0; Faction::MAX];
+        let mut spawners = [0; Faction::MAX];
+        cells[0] = 10;
+        spawners[0] = 1;
+        treasury.collect(4.0, &cells, &spawners);
+        assert_eq!(treasury.gold(Faction(0)), 50);
+        treasury.collect(10.0, &cells, &spawners);
+        assert_eq!(treasury.gold(Faction(0)), 80);
+        assert_eq!(treasury.gold(Faction(1)), 50);
+    }
+
+    #[test]
+    #[serial]
+    fn bought_unit_comes_out_of_the_spawner() {
+        fn init(mut commands: Commands, mut player_commands: ResMut<PlayerCommands>) {
+            commands
+                .spawn()
+                .insert(SpawnInfo {
+                    target_unit_count: None,
+                    spawn_delay: None,
+                    last_spawn: 0.0,
+                    faction: Faction(0),
+                    x: 0,
+                    y: 0,
+                    template: UnitTemplate::default(),
+                })
+                .insert(SpawnQueue::new(Faction(0)));
+            player_commands.push(PlayerCommand::Buy {
+                faction: Faction(0),
+                x: 0,
+                y: 0,
+                archetype: ArchetypeId::new("archer"),
+            });
+        }
+
+        fn check_unit(
+            mut flag: ResMut<TestCheck<bool>>,
+            treasury: Res<Treasury>,
+            query: Query<&ArchetypeId>,
+        ) {
+            **flag = treasury.gold(Faction(0)) == 20
+                && query.iter().any(|id| *id == ArchetypeId::new("archer"));
+        }
+
+        App::new()
+            .add_plugin(Test::Time(0.5))
+            .add_plugin(SimPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(CommandPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(UnitPlugin)
+            .add_plugin(SpawnPlugin)
+            .add_plugin(EconomyPlugin)
+            .add_system(init_cameras_2d)
+            .insert_resource(Grid::new(2, 2))
+            .insert_resource(treasury())
+            .insert_resource(TestCheck::new(false).is_true())
+            .add_startup_system(init)
+            .add_system(check_unit)
+            .run();
+    }
+}
@@ -0,0 +1,135 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+
+/// One side of a match. Factions on the same team in [Alliances] don't fight each other.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize,
+)]
+#[serde(transparent)]
+pub struct Faction(pub u8);
+
+impl Faction {
+    pub const MAX: usize = 8;
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn all() -> impl Iterator<Item = Faction> {
+        (0..Self::MAX as u8).map(Faction)
+    }
+
+    pub fn color(&self) -> Color {
+        match self.0 % Self::MAX as u8 {
+            0 => Color::rgb(0.0, 1.0, 1.0),
+            1 => Color::rgb(1.0, 1.0, 0.0),
+            2 => Color::rgb(1.0, 0.3, 0.3),
+            3 => Color::rgb(0.4, 0.4, 1.0),
+            4 => Color::rgb(1.0, 0.5, 0.0),
+            5 => Color::rgb(0.8, 0.3, 1.0),
+            6 => Color::rgb(0.3, 1.0, 0.3),
+            _ => Color::rgb(1.0, 0.6, 0.8),
+        }
+    }
+
+    pub fn spritesheet(&self) -> &'static str {
+        match self.0 % Self::MAX as u8 {
+            0 => "spritesheet/Female/Female 12-3.png",
+            1 => "spritesheet/Soldier/Soldier 01-1.png",
+            2 => "spritesheet/Male/Male 01-1.png",
+            3 => "spritesheet/Enemy/Enemy 01-1.png",
+            4 => "spritesheet/Female/Female 01-1.png",
+            5 => "spritesheet/Soldier/Soldier 02-1.png",
+            6 => "spritesheet/Male/Male 02-1.png",
+            _ => "spritesheet/Enemy/Enemy 02-1.png",
+        }
+    }
+}
+
+/// Team of every faction. By default each faction is alone, which gives a free for all.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Alliances {
+    teams: [Option<u8>; Faction::MAX],
+}
+
+impl Alliances {
+    pub fn free_for_all() -> Self {
+        Self::default()
+    }
+
+    /// Faction `i` joins the team `teams[i]`, the factions not listed stay alone.
+    pub fn from_teams(teams: &[u8]) -> Self {
+        let mut alliances = Self::default();
+        for (i, team) in teams.iter().enumerate().take(Faction::MAX) {
+            alliances.teams[i] = Some(*team);
+        }
+        alliances
+    }
+
+    pub fn set_team(&mut self, faction: Faction, team: Option<u8>) {
+        if let Some(t) = self.teams.get_mut(faction.index()) {
+            *t = team;
+        }
+    }
+
+    pub fn team(&self, faction: Faction) -> Option<u8> {
+        self.teams.get(faction.index()).cloned().flatten()
+    }
+
+    pub fn are_allies(&self, a: Faction, b: Faction) -> bool {
+        a == b || (self.team(a).is_some() && self.team(a) == self.team(b))
+    }
+
+    pub fn are_hostile(&self, a: Faction, b: Faction) -> bool {
+        !self.are_allies(a, b)
+    }
+
+    pub fn side(&self, faction: Faction) -> Side {
+        match self.team(faction) {
+            Some(team) => Side::Team(team),
+            None => Side::Alone(faction),
+        }
+    }
+}
+
+/// Team of a faction, or the faction itself when it fights alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Team(u8),
+    Alone(Faction),
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Side::Team(team) => write!(f, "team {}", team),
+            Side::Alone(faction) => write!(f, "faction {}", faction.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_free_for_all() {
+        let alliances = Alliances::default();
+        for a in Faction::all() {
+            for b in Faction::all() {
+                assert_eq!(alliances.are_hostile(a, b), a != b);
+            }
+        }
+    }
+
+    #[test]
+    fn two_vs_two() {
+        let alliances = Alliances::from_teams(&[0, 0, 1, 1]);
+        assert!(alliances.are_allies(Faction(0), Faction(1)));
+        assert!(alliances.are_allies(Faction(2), Faction(3)));
+        assert!(alliances.are_hostile(Faction(1), Faction(2)));
+        assert!(alliances.are_hostile(Faction(0), Faction(4)));
+    }
+}
@@ -23,6 +23,10 @@ pub struct FxSpawnEvent {
 pub enum FxKind {
     Death,
     Fire,
+    /// A ranged unit shooting.
+    Muzzle,
+    /// A projectile landing.
+    Impact,
 }
 
 #[derive(Component)]
@@ -31,6 +35,8 @@ struct Fx;
 struct SpawnFXData {
     fire_handle: Handle<TextureAtlas>,
     death_handle: Handle<TextureAtlas>,
+    muzzle_handle: Handle<TextureAtlas>,
+    impact_handle: Handle<TextureAtlas>,
 }
 
 impl FromWorld for SpawnFXData {
@@ -38,6 +44,8 @@ impl FromWorld for SpawnFXData {
         let asset_server = world.get_resource::<AssetServer>().unwrap();
         let fire = asset_server.load("spritesheet/effects/MagicBarrier_64x64.png");
         let death = asset_server.load("spritesheet/effects/explosion.png");
+        let muzzle = asset_server.load("spritesheet/effects/FireCast_96x96.png");
+        let impact = asset_server.load("spritesheet/effects/Explosion_2_64x64.png");
 
         let mut texture_atlas_asset = world.get_resource_mut::<Assets<TextureAtlas>>().unwrap();
 
@@ -45,9 +53,15 @@ impl FromWorld for SpawnFXData {
         let fire_handle = texture_atlas_asset.add(fire_atlas);
         let death_atlas = TextureAtlas::from_grid(death, Vec2::new(64.0, 64.0), 4, 4);
         let death_handle = texture_atlas_asset.add(death_atlas);
+        let muzzle_atlas = TextureAtlas::from_grid(muzzle, Vec2::new(96.0, 96.0), 28, 1);
+        let muzzle_handle = texture_atlas_asset.add(muzzle_atlas);
+        let impact_atlas = TextureAtlas::from_grid(impact, Vec2::new(64.0, 64.0), 44, 1);
+        let impact_handle = texture_atlas_asset.add(impact_atlas);
         Self {
             fire_handle: fire_handle.clone(),
             death_handle: death_handle.clone(),
+            muzzle_handle,
+            impact_handle,
         }
     }
 }
@@ -63,11 +77,15 @@ fn spawn_fx_system(
         let texture_atlas = match event.kind {
             FxKind::Fire => &data.fire_handle,
             FxKind::Death => &data.death_handle,
+            FxKind::Muzzle => &data.muzzle_handle,
+            FxKind::Impact => &data.impact_handle,
         }
         .clone();
         let animation = match event.kind {
             FxKind::Fire => Animation::new(AnimationMode::Stop, (0..33).collect()),
             FxKind::Death => Animation::new(AnimationMode::Stop, (0..16).collect()),
+            FxKind::Muzzle => Animation::new(AnimationMode::Stop, (0..28).collect()),
+            FxKind::Impact => Animation::new(AnimationMode::Stop, (0..44).collect()),
         };
         let mut transform = event.transform.clone();
         transform.translation.z += 1.0;
@@ -1,43 +1,90 @@
 //! # Multi warrior library
 //!
 //! The plugin Game is the main one and include everything else needed to run the game.
+//! [Headless] runs the same battles without window for batch simulations.
 use bevy::prelude::*;
 
 mod anim;
+mod archetype;
+mod bindings;
+mod building;
 mod button;
 mod camera;
+mod command;
+mod damage;
+mod economy;
+mod faction;
 mod fps;
 mod fx;
 mod grid;
+mod headless;
 mod input;
+mod path;
+mod picking;
+mod player;
+mod projectile;
+mod replay;
+mod scenario;
+mod selection;
+mod sim;
 mod spawn;
+mod state;
+mod template;
+mod territory;
 mod unit;
 mod utils;
+mod victory;
 
 use anim::*;
+use archetype::ArchetypePlugin;
+use bindings::BindingsPlugin;
+use building::BuildingPlugin;
 use button::*;
 use camera::*;
+use command::CommandPlugin;
+use economy::{EconomyHudPlugin, EconomyPlugin};
 use fps::FPSPlugin;
 use fx::FxPlugin;
 use grid::*;
 use input::InputPlugin;
+use picking::PickingPlugin;
+use player::PlayerPlugin;
+use projectile::ProjectilePlugin;
+use replay::ReplayPlugin;
+use scenario::ScenarioPlugin;
+use selection::SelectionPlugin;
+use sim::SimPlugin;
+use spawn::SpawnPlugin;
+use state::StatePlugin;
+use territory::{TerritoryHudPlugin, TerritoryPlugin};
 use unit::*;
 use utils::Direction;
 
+pub use command::{PlayerCommand, PlayerCommands};
+pub use headless::{run_headless, BattleOutcome, Headless, HeadlessResult};
+pub use replay::{Replay, ReplayMode};
+pub use scenario::CurrentScenario;
+pub use sim::{SimClock, SimRng};
+
 pub struct Game;
 
 impl Plugin for Game {
     fn build(&self, app: &mut App) {
         app.add_plugins(DefaultPlugins)
             .add_plugin(FPSPlugin { color: Color::BLACK })
-            .add_plugin(UnitPlugin::default())
-            .add_plugin(GridPlugin::default())
+            .add_plugin(Simulation)
             .add_plugin(InputPlugin::default())
+            .add_plugin(BindingsPlugin)
             .add_plugin(ButtonPlugin::default())
             .add_plugin(FxPlugin)
-            .insert_resource(Grid::new(10, 10))
+            .add_plugin(StatePlugin)
+            .add_plugin(TerritoryHudPlugin)
+            .add_plugin(EconomyHudPlugin)
+            .add_plugin(PlayerPlugin)
+            .add_plugin(PickingPlugin)
+            .add_plugin(SelectionPlugin)
+            .add_plugin(CameraControlPlugin)
             .add_startup_system(init_cameras)
-            .add_startup_system(init_stuff)
 
             //.add_system(change_grid_randomly)
             .add_system(on_button_click)
@@ -47,30 +94,23 @@ impl Plugin for Game {
     }
 }
 
-fn init_stuff(
-    mut commands: Commands,
-    asset_server: ResMut<AssetServer>,
-    mut grid: ResMut<Grid>,
-    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
-) {
-    for i in 1..8 {
-        spawn_unit(
-            &mut commands,
-            &asset_server,
-            &mut grid,
-            &mut texture_atlases,
-            i,
-            i,
-            false,
-            |c| {
-                c.insert(MoveOnForceAI {
-                    target_x: (i ^ 2) % 10,
-                    target_y: (i ^ 3) % 10,
-                    //stick_to_target: true,
-                    ..Default::default()
-                });
-            },
-        )
+/// Battle logic shared by [Game] and [Headless], it doesn't need a window.
+pub struct Simulation;
+
+impl Plugin for Simulation {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(SimPlugin)
+            .add_plugin(ReplayPlugin)
+            .add_plugin(CommandPlugin)
+            .add_plugin(ArchetypePlugin)
+            .add_plugin(UnitPlugin::default())
+            .add_plugin(ProjectilePlugin)
+            .add_plugin(GridPlugin::default())
+            .add_plugin(SpawnPlugin)
+            .add_plugin(BuildingPlugin)
+            .add_plugin(TerritoryPlugin)
+            .add_plugin(EconomyPlugin)
+            .add_plugin(ScenarioPlugin);
     }
 }
 
@@ -1,6 +1,7 @@
 use bevy::prelude::*;
 
-use crate::camera::*;
+use crate::bindings::*;
+use crate::faction::*;
 
 #[derive(Default)]
 pub struct GridPlugin;
@@ -8,7 +9,8 @@ pub struct GridPlugin;
 impl Plugin for GridPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<GridRenderDebug>()
-            .add_startup_system(init_render_grid)
+            .init_resource::<Bindings>()
+            .add_system_to_stage(CoreStage::PreUpdate, sync_render_grid)
             .add_system(update_grid_debug_visible)
             .add_system(update_grid_render_debug)
             .add_system(update_grid_transform)
@@ -18,10 +20,9 @@ impl Plugin for GridPlugin {
 
 #[derive(Component)]
 pub struct GridRenderDebug {
-    nothing_color: Handle<ColorMaterial>,
-    friend_color: Handle<ColorMaterial>,
-    enemy_color: Handle<ColorMaterial>,
+    nothing_color: Color,
     visible: bool,
+    node_count: (i32, i32),
 
     left: f32,
     right: f32,
@@ -42,11 +43,38 @@ impl GridRenderDebug {
         let starty = self.bottom + self.height * (y + 0.5);
         Vec3::new(startx, starty, -starty / 10000.0)
     }
+
+    /// Corners of the grid in the world, bottom left then top right.
+    pub fn area(&self) -> (Vec2, Vec2) {
+        (
+            Vec2::new(self.left, self.bottom),
+            Vec2::new(self.right, self.top),
+        )
+    }
+
+    /// Cell under a point of the world, the inverse of [GridRenderDebug::pos]. It can be out of
+    /// the grid.
+    pub fn cell_at(&self, pos: Vec2) -> (i32, i32) {
+        (
+            ((pos.x - self.left) / self.width).floor() as i32,
+            ((pos.y - self.bottom) / self.height).floor() as i32,
+        )
+    }
 }
 
 #[derive(Component)]
 struct GridRenderDebugNode;
 
+/// Side of a cell in world units.
+pub const CELL_SIZE: f32 = 64.0;
+
+// Keep the grid nodes behind every unit, inside the default 2d camera range
+const GRID_NODE_Z: f32 = -0.09;
+
+/// Depth to draw at instead of the one given by the row, for what stays behind the units.
+#[derive(Component)]
+pub struct GridLayer(pub f32);
+
 #[derive(Component)]
 pub struct GridTransform {
     pub x: f32,
@@ -64,14 +92,12 @@ impl GridTransform {
     }
 }
 
-impl FromWorld for GridRenderDebug {
-    fn from_world(world: &mut World) -> Self {
-        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+impl Default for GridRenderDebug {
+    fn default() -> Self {
         Self {
-            nothing_color: materials.add(Color::rgb(1.0, 1.0, 1.0).into()),
-            friend_color: materials.add(Color::rgb(0.0, 1.0, 1.0).into()),
-            enemy_color: materials.add(Color::rgb(1.0, 1.0, 0.0).into()),
+            nothing_color: Color::rgb(1.0, 1.0, 1.0),
             visible: false,
+            node_count: (0, 0),
 
             left: 0.0,
             right: 0.0,
@@ -84,7 +110,21 @@ impl FromWorld for GridRenderDebug {
     }
 }
 
-fn init_render_grid(mut commands: Commands, grid: Res<Grid>) {
+/// Create one node per cell, again when the grid is resized by a new scenario.
+fn sync_render_grid(
+    mut commands: Commands,
+    grid: Res<Grid>,
+    mut info: ResMut<GridRenderDebug>,
+    nodes: Query<Entity, With<GridRenderDebugNode>>,
+) {
+    if info.node_count == (grid.x, grid.y) {
+        return;
+    }
+    info.node_count = (grid.x, grid.y);
+
+    for node in nodes.iter() {
+        commands.entity(node).despawn();
+    }
     for x in 0..grid.x {
         for y in 0..grid.y {
             commands
@@ -93,40 +133,49 @@ fn init_render_grid(mut commands: Commands, grid: Res<Grid>) {
                     ..Default::default()
                 })
                 .insert(GridRenderDebugNode)
+                .insert(GridLayer(GRID_NODE_Z))
                 .insert(GridTransform::on(x, y));
         }
     }
 }
 
-fn update_grid_debug_visible(input: Res<Input<KeyCode>>, mut info: ResMut<GridRenderDebug>) {
-    if input.just_pressed(KeyCode::G) {
+fn update_grid_debug_visible(
+    bindings: Res<Bindings>,
+    input: Res<Input<KeyCode>>,
+    mut info: ResMut<GridRenderDebug>,
+) {
+    if bindings.just_pressed(&input, Action::GridDebug) {
         info.visible = !info.visible;
         info!("Changing grid debug visibility to {}", info.visible);
     }
 }
 
-fn update_grid_render_debug(
-    grid: Res<Grid>,
-    mut info: ResMut<GridRenderDebug>,
-    query: Query<(&MainCamera, &OrthographicProjection)>,
-) {
-    for (_camera, proj) in query.iter() {
-        info.left = proj.left;
-        info.right = proj.right;
-        info.top = proj.top;
-        info.bottom = proj.bottom;
-
-        info.width = (info.right - info.left) / grid.x as f32;
-        info.height = (info.top - info.bottom) / grid.y as f32;
+/// The cells keep their size in the world whatever the size of the grid, the cameras move over
+/// it, so this does not depend on how many views there are. The grid is centered on the origin.
+fn update_grid_render_debug(grid: Res<Grid>, mut info: ResMut<GridRenderDebug>) {
+    let half_width = grid.x as f32 * CELL_SIZE / 2.0;
+    let half_height = grid.y as f32 * CELL_SIZE / 2.0;
+    if info.right == half_width && info.top == half_height && info.width == CELL_SIZE {
+        return;
     }
+    info.left = -half_width;
+    info.right = half_width;
+    info.top = half_height;
+    info.bottom = -half_height;
+
+    info.width = CELL_SIZE;
+    info.height = CELL_SIZE;
 }
 
 fn update_grid_transform(
     info: Res<GridRenderDebug>,
-    mut query: Query<(&GridTransform, &mut Transform)>,
+    mut query: Query<(&GridTransform, &mut Transform, Option<&GridLayer>)>,
 ) {
-    for (node, mut transform) in query.iter_mut() {
+    for (node, mut transform, layer) in query.iter_mut() {
         transform.translation = info.pos(node.x, node.y);
+        if let Some(layer) = layer {
+            transform.translation.z = layer.0;
+        }
         if node.update_scale {
             transform.scale = info.scale();
         }
@@ -136,27 +185,33 @@ fn update_grid_transform(
 fn update_grid_color(
     grid: Res<Grid>,
     grid_debug: Res<GridRenderDebug>,
-    mut query_materials: Query<
-        (&GridTransform, &mut Handle<ColorMaterial>, &mut Visibility),
+    mut query_nodes: Query<
+        (&GridTransform, &mut Sprite, &mut Visibility),
         With<GridRenderDebugNode>,
     >,
 ) {
-    if grid_debug.visible {
-        for (node, mut material, mut draw) in query_materials.iter_mut() {
-            let status = grid.get_status(node.x as i32, node.y as i32);
-            let target_material = match status {
-                Some(GridStatus::Friend) => &grid_debug.friend_color,
-                Some(GridStatus::Enemy) => &grid_debug.enemy_color,
-                _ => &grid_debug.nothing_color,
-            };
-            if *material != *target_material {
-                *material = target_material.clone();
+    for (node, mut sprite, mut draw) in query_nodes.iter_mut() {
+        let (x, y) = (node.x as i32, node.y as i32);
+        let terrain = grid.get_terrain(x, y).unwrap_or_default();
+        let target_color = if grid_debug.visible {
+            match grid.get_status(x, y) {
+                Some(GridStatus::Held(faction)) => Some(faction.color()),
+                _ if terrain == Terrain::Plain => Some(grid_debug.nothing_color),
+                _ => Some(terrain.color()),
+            }
+        } else if terrain != Terrain::Plain {
+            Some(terrain.color())
+        } else {
+            None
+        };
+
+        if let Some(color) = target_color {
+            if sprite.color != color {
+                sprite.color = color;
             }
-            draw.is_visible = true;
         }
-    } else {
-        for (_, _, mut draw) in query_materials.iter_mut() {
-            draw.is_visible = false;
+        if draw.is_visible != target_color.is_some() {
+            draw.is_visible = target_color.is_some();
         }
     }
 }
@@ -172,44 +227,99 @@ pub fn change_grid_randomly(mut grid: ResMut<Grid>) {
     let random_y = (random::<u16>() % max_y) as i32;
 
     let random_change = (random::<u16>() % 3) as i32 - 1;
+    let random_faction = Faction(random::<u8>() % 2);
 
-    grid.change_by_count(random_x, random_y, random_change);
+    grid.change_by_count(random_x, random_y, random_faction, random_change);
 }
 
 pub struct Grid {
-    people_by_case: Vec<i32>,
+    people_by_case: Vec<Occupants>,
+    terrain: Vec<Terrain>,
+    changed_cells: Vec<(i32, i32)>,
     pub x: i32,
     pub y: i32,
 }
 
-#[derive(Debug, PartialEq)]
+/// Units standing on a cell. A cell can only be held by one faction at a time.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Occupants {
+    faction: Faction,
+    count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum GridStatus {
-    Friend,
     Neutral,
-    Enemy,
+    Held(Faction),
 }
 
-impl GridStatus {
-    #[allow(dead_code)]
-    pub fn from_force(ally: bool) -> Self {
-        if ally {
-            Self::Friend
-        } else {
-            Self::Enemy
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Terrain {
+    #[default]
+    Plain,
+    Wall,
+    Water,
+    Forest,
+}
+
+impl Terrain {
+    /// Character used for the terrain in the scenario maps.
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '.' => Some(Terrain::Plain),
+            '#' => Some(Terrain::Wall),
+            '~' => Some(Terrain::Water),
+            'T' => Some(Terrain::Forest),
+            _ => None,
         }
     }
+
+    /// Cost of walking into a cell of this terrain, None when it can't be crossed.
+    pub fn movement_cost(&self) -> Option<i32> {
+        match self {
+            Terrain::Plain => Some(1),
+            Terrain::Forest => Some(2),
+            Terrain::Water => Some(3),
+            Terrain::Wall => None,
+        }
+    }
+
+    /// Chance for an attack on a unit standing on this terrain to miss.
+    pub fn cover(&self) -> f32 {
+        match self {
+            Terrain::Forest => 0.3,
+            _ => 0.0,
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Terrain::Plain => Color::rgb(0.45, 0.7, 0.35),
+            Terrain::Wall => Color::rgb(0.3, 0.3, 0.3),
+            Terrain::Water => Color::rgb(0.2, 0.4, 0.9),
+            Terrain::Forest => Color::rgb(0.1, 0.45, 0.15),
+        }
+    }
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Grid::new(0, 0)
+    }
 }
 
 impl Grid {
     pub fn new(x: i32, y: i32) -> Grid {
         Grid {
-            people_by_case: vec![0; (x * y) as usize],
+            people_by_case: vec![Occupants::default(); (x * y) as usize],
+            terrain: vec![Terrain::default(); (x * y) as usize],
+            changed_cells: Vec::new(),
             x: x,
             y: y,
         }
     }
 
-    fn to_pos(self: &Grid, x: i32, y: i32) -> Option<usize> {
+    pub(crate) fn to_pos(self: &Grid, x: i32, y: i32) -> Option<usize> {
         if 0 <= x && x < self.x && 0 <= y && y < self.y {
             Some((x * self.y + y) as usize)
         } else {
@@ -217,58 +327,120 @@ impl Grid {
         }
     }
 
-    #[allow(dead_code)]
-    pub fn add_friend(self: &mut Grid, x: i32, y: i32) -> bool {
+    /// Add a unit of the faction on the cell if it isn't held by another faction.
+    pub fn add_unit(self: &mut Grid, x: i32, y: i32, faction: Faction) -> bool {
         if let Some(pos) = self.to_pos(x, y) {
-            if self.people_by_case[pos] >= 0 {
-                self.people_by_case[pos] += 1;
+            let cell = &mut self.people_by_case[pos];
+            if cell.count == 0 || cell.faction == faction {
+                cell.faction = faction;
+                cell.count += 1;
+                self.changed_cells.push((x, y));
                 return true;
             }
         }
         return false;
     }
 
-    #[allow(dead_code)]
-    pub fn add_enemy(self: &mut Grid, x: i32, y: i32) -> bool {
+    pub fn change_by_count(self: &mut Grid, x: i32, y: i32, faction: Faction, change: i32) {
         if let Some(pos) = self.to_pos(x, y) {
-            if self.people_by_case[pos] <= 0 {
-                self.people_by_case[pos] -= 1;
-                return true;
+            let cell = &mut self.people_by_case[pos];
+            if cell.count == 0 {
+                cell.faction = faction;
+            }
+            if cell.faction != faction {
+                warn!(
+                    "{:?} tried to change ({}, {}) held by {:?}",
+                    faction, x, y, cell.faction
+                );
+                return;
             }
+            cell.count = (cell.count as i32 + change).max(0) as u32;
+            self.changed_cells.push((x, y));
         }
-        return false;
     }
 
-    pub fn change_by_count(self: &mut Grid, x: i32, y: i32, change: i32) {
-        if let Some(pos) = self.to_pos(x, y) {
-            self.people_by_case[pos] += change;
-        }
+    /// Cells touched since the last call, used to invalidate cached paths.
+    pub fn take_changed_cells(self: &mut Grid) -> Vec<(i32, i32)> {
+        std::mem::take(&mut self.changed_cells)
     }
 
     pub fn get_status(self: &Grid, x: i32, y: i32) -> Option<GridStatus> {
-        self.get_count(x, y).map(|count| {
-            if count == 0 {
+        self.to_pos(x, y).map(|pos| {
+            let cell = &self.people_by_case[pos];
+            if cell.count == 0 {
                 GridStatus::Neutral
-            } else if count < 0 {
-                GridStatus::Enemy
             } else {
-                GridStatus::Friend
+                GridStatus::Held(cell.faction)
             }
         })
     }
 
-    pub fn get_count(self: &Grid, x: i32, y: i32) -> Option<i32> {
+    pub fn get_count(self: &Grid, x: i32, y: i32) -> Option<u32> {
         if let Some(pos) = self.to_pos(x, y) {
-            return Some(self.people_by_case[pos]);
+            return Some(self.people_by_case[pos].count);
         }
         return None;
     }
+
+    /// Faction holding the cell, None when it's empty or outside the grid.
+    pub fn get_faction(self: &Grid, x: i32, y: i32) -> Option<Faction> {
+        match self.get_status(x, y) {
+            Some(GridStatus::Held(faction)) => Some(faction),
+            _ => None,
+        }
+    }
+
+    /// Number of cells held by each faction.
+    pub fn cells_by_faction(self: &Grid) -> [u32; Faction::MAX] {
+        let mut cells = [0; Faction::MAX];
+        for cell in self.people_by_case.iter().filter(|cell| cell.count > 0) {
+            if let Some(count) = cells.get_mut(cell.faction.index()) {
+                *count += 1;
+            }
+        }
+        cells
+    }
+
+    pub fn walkable_cells(self: &Grid) -> u32 {
+        self.terrain
+            .iter()
+            .filter(|terrain| terrain.movement_cost().is_some())
+            .count() as u32
+    }
+
+    pub fn set_terrain(self: &mut Grid, x: i32, y: i32, terrain: Terrain) -> bool {
+        if let Some(pos) = self.to_pos(x, y) {
+            self.terrain[pos] = terrain;
+            self.changed_cells.push((x, y));
+            return true;
+        }
+        false
+    }
+
+    pub fn get_terrain(self: &Grid, x: i32, y: i32) -> Option<Terrain> {
+        self.to_pos(x, y).map(|pos| self.terrain[pos])
+    }
+
+    /// Cost of walking into the cell, None if it is outside the grid or can't be crossed.
+    pub fn movement_cost(self: &Grid, x: i32, y: i32) -> Option<i32> {
+        self.get_terrain(x, y).and_then(|t| t.movement_cost())
+    }
+
+    pub fn is_walkable(self: &Grid, x: i32, y: i32) -> bool {
+        self.movement_cost(x, y).is_some()
+    }
+
+    pub fn cover(self: &Grid, x: i32, y: i32) -> f32 {
+        self.get_terrain(x, y).map(|t| t.cover()).unwrap_or(0.0)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::camera::*;
     use crate::utils::tests::*;
+    use bevy::render::view::VisibleEntities;
 
     /*
     #[test]
@@ -363,8 +535,8 @@ mod tests {
 
         for i in 0..x {
             for j in 0..y {
-                assert!(grid.add_friend(i, j), "Friend ({}, {})", i, j);
-                assert!(!grid.add_enemy(i, j), "Enemy ({}, {})", i, j);
+                assert!(grid.add_unit(i, j, Faction(0)), "Friend ({}, {})", i, j);
+                assert!(!grid.add_unit(i, j, Faction(1)), "Enemy ({}, {})", i, j);
             }
         }
     }
@@ -377,32 +549,63 @@ mod tests {
 
         for i in 0..x {
             for j in 0..y {
-                assert!(grid.add_enemy(i, j), "Enemy ({}, {})", i, j);
-                assert!(!grid.add_friend(i, j), "Friend ({}, {})", i, j);
+                assert!(grid.add_unit(i, j, Faction(1)), "Enemy ({}, {})", i, j);
+                assert!(!grid.add_unit(i, j, Faction(0)), "Friend ({}, {})", i, j);
             }
         }
     }
 
+    #[test]
+    fn many_factions_can_hold_cells() {
+        let mut grid = Grid::new(Faction::MAX as i32, 1);
+        for faction in Faction::all() {
+            assert!(grid.add_unit(faction.0 as i32, 0, faction));
+        }
+        for faction in Faction::all() {
+            assert_eq!(grid.get_faction(faction.0 as i32, 0), Some(faction));
+        }
+    }
+
     #[test]
     fn grid_get_count_return_get_by_count() {
         let mut grid = Grid::new(1, 1);
         let mut expected = 0;
 
         for i in 1..3 {
-            grid.change_by_count(0, 0, i);
-            expected += i;
+            grid.change_by_count(0, 0, Faction(0), i);
+            expected += i as u32;
             assert_eq!(grid.get_count(0, 0).unwrap(), expected);
-            assert!(grid.get_status(0, 0).unwrap() == GridStatus::Friend);
+            assert!(grid.get_status(0, 0).unwrap() == GridStatus::Held(Faction(0)));
         }
 
-        for i in (-10..-7).rev() {
-            grid.change_by_count(0, 0, i);
-            expected += i;
-            assert_eq!(grid.get_count(0, 0).unwrap(), expected);
-            assert!(grid.get_status(0, 0).unwrap() == GridStatus::Enemy);
+        // Another faction can't change a held cell
+        grid.change_by_count(0, 0, Faction(2), 5);
+        assert_eq!(grid.get_count(0, 0).unwrap(), expected);
+
+        grid.change_by_count(0, 0, Faction(0), -(expected as i32));
+        assert!(grid.get_status(0, 0).unwrap() == GridStatus::Neutral);
+
+        for i in 1..3 {
+            grid.change_by_count(0, 0, Faction(2), i);
+            assert!(grid.get_status(0, 0).unwrap() == GridStatus::Held(Faction(2)));
         }
     }
 
+    #[test]
+    fn walls_are_not_walkable() {
+        let mut grid = Grid::new(2, 2);
+        assert!(grid.set_terrain(1, 1, Terrain::Wall));
+        assert!(grid.set_terrain(0, 1, Terrain::Water));
+        assert!(!grid.set_terrain(2, 1, Terrain::Wall));
+
+        assert!(!grid.is_walkable(1, 1));
+        assert!(grid.is_walkable(0, 1));
+        assert!(!grid.is_walkable(-1, 0));
+        assert_eq!(grid.movement_cost(0, 0), Some(1));
+        assert_eq!(grid.movement_cost(0, 1), Some(3));
+        assert_eq!(grid.get_terrain(1, 1), Some(Terrain::Wall));
+    }
+
     #[test]
     fn grid_to_pos_is_none_on_empty_grid() {
         let grid = Grid::new(0, 0);
@@ -431,4 +634,21 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn cell_at_is_the_inverse_of_pos() {
+        let info = GridRenderDebug {
+            left: -100.0,
+            bottom: -50.0,
+            width: 20.0,
+            height: 10.0,
+            ..Default::default()
+        };
+        for (x, y) in [(0, 0), (3, 7), (9, 9)] {
+            let pos = info.pos(x as f32, y as f32);
+            assert_eq!(info.cell_at(pos.truncate()), (x, y));
+            assert_eq!(info.cell_at(pos.truncate() + Vec2::new(9.0, -4.0)), (x, y));
+        }
+        assert_eq!(info.cell_at(Vec2::new(-101.0, 0.0)), (-1, 5));
+    }
 }
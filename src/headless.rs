@@ -0,0 +1,254 @@
+//! Run a battle without window nor GPU and print who is left standing.
+//!
+//! Useful to batch simulate scenarios on machines without a display:
+//! `multi_warrior --headless --seconds 60 --scenario scenarios/skirmish.scenario.ron`
+use bevy::app::AppExit;
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
+use bevy::ecs::event::ManualEventReader;
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+
+use std::fmt;
+
+use crate::building::Building;
+use crate::faction::*;
+use crate::fx::FxSpawnEvent;
+use crate::scenario::CurrentScenario;
+use crate::sim::{SimClock, SimRng};
+use crate::spawn::SpawnInfo;
+use crate::unit::UnitForce;
+use crate::Simulation;
+
+/// Plugin replacing [crate::Game] when there is no display. The battle is simulated as fast as
+/// possible with [run_headless], the app stops after `seconds` of battle and leaves a
+/// [HeadlessResult] in the world. The logs are left to the app.
+pub struct Headless {
+    pub seconds: f32,
+}
+
+impl Plugin for Headless {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SimClock::one_step_per_frame())
+            .add_plugins(MinimalPlugins)
+            .add_plugin(bevy::transform::TransformPlugin::default())
+            .add_plugin(bevy::hierarchy::HierarchyPlugin::default())
+            .add_plugin(bevy::input::InputPlugin::default())
+            .add_plugin(bevy::asset::AssetPlugin::default())
+            // Units still ask for their sprites, give them empty images instead of a renderer
+            .add_asset::<Image>()
+            .add_asset::<TextureAtlas>()
+            .init_asset_loader::<NoImageLoader>()
+            .add_event::<FxSpawnEvent>()
+            .add_plugin(Simulation)
+            .insert_resource(HeadlessTimer {
+                seconds: self.seconds,
+            })
+            .insert_resource(HeadlessResult::Running)
+            .add_system_to_stage(CoreStage::PostUpdate, end_headless_battle);
+    }
+}
+
+/// How the headless battle went, read it after [run_headless] returned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeadlessResult {
+    Running,
+    Finished(BattleOutcome),
+    Failed(String),
+}
+
+/// Units left for every faction when the battle stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BattleOutcome {
+    pub seed: u64,
+    pub seconds: f32,
+    pub units: [u32; Faction::MAX],
+    pub spawners: [u32; Faction::MAX],
+    pub alliances: Alliances,
+}
+
+impl BattleOutcome {
+    /// Teams or lone factions still having units.
+    fn sides_alive(&self) -> Vec<Side> {
+        let mut sides = Vec::new();
+        for faction in Faction::all() {
+            if self.units[faction.index()] == 0 {
+                continue;
+            }
+            let side = self.alliances.side(faction);
+            if !sides.contains(&side) {
+                sides.push(side);
+            }
+        }
+        sides
+    }
+
+    pub fn winner(&self) -> Option<String> {
+        match self.sides_alive().as_slice() {
+            [side] => Some(side.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BattleOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Battle stopped after {:.1}s (seed {})",
+            self.seconds, self.seed
+        )?;
+        for faction in Faction::all() {
+            let (units, spawners) = (self.units[faction.index()], self.spawners[faction.index()]);
+            if units == 0 && spawners == 0 {
+                continue;
+            }
+            write!(f, "faction {}", faction.0)?;
+            if let Some(team) = self.alliances.team(faction) {
+                write!(f, " (team {})", team)?;
+            }
+            writeln!(f, ": {} units, {} spawners", units, spawners)?;
+        }
+        match (self.winner(), self.sides_alive().len()) {
+            (Some(winner), _) => write!(f, "Winner: {}", winner),
+            (None, 0) => write!(f, "No survivor"),
+            (None, sides) => write!(f, "Undecided, {} sides left", sides),
+        }
+    }
+}
+
+/// Update the app until the battle is over. Unlike [App::run], which moves the world into the
+/// runner, the world is kept so the [HeadlessResult] can be read afterwards.
+pub fn run_headless(app: &mut App) {
+    let mut exits = ManualEventReader::<AppExit>::default();
+    loop {
+        app.update();
+        let events = app.world.resource::<Events<AppExit>>();
+        if exits.iter(events).next().is_some() {
+            return;
+        }
+    }
+}
+
+struct HeadlessTimer {
+    seconds: f32,
+}
+
+#[derive(Default)]
+struct NoImageLoader;
+
+impl AssetLoader for NoImageLoader {
+    fn load<'a>(
+        &'a self,
+        _bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            load_context.set_default_asset(LoadedAsset::new(Image::default()));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["png"]
+    }
+}
+
+/// Stop the app when the battle is over, or when the scenario can't be loaded.
+#[allow(clippy::too_many_arguments)]
+fn end_headless_battle(
+    clock: Res<SimClock>,
+    rng: Res<SimRng>,
+    asset_server: Res<AssetServer>,
+    scenario: Res<CurrentScenario>,
+    alliances: Res<Alliances>,
+    timer: Res<HeadlessTimer>,
+    mut result: ResMut<HeadlessResult>,
+    mut exit: EventWriter<AppExit>,
+    units: Query<&UnitForce, Without<Building>>,
+    spawners: Query<&SpawnInfo>,
+) {
+    if *result != HeadlessResult::Running {
+        return;
+    }
+    match scenario.load_state(&asset_server) {
+        LoadState::Loaded => {}
+        LoadState::Failed | LoadState::Unloaded => {
+            *result = HeadlessResult::Failed(format!("could not load {}", scenario.path));
+            exit.send(AppExit);
+            return;
+        }
+        LoadState::NotLoaded | LoadState::Loading => return,
+    }
+
+    if clock.elapsed() < timer.seconds {
+        return;
+    }
+
+    let mut outcome = BattleOutcome {
+        seed: rng.seed(),
+        seconds: clock.elapsed(),
+        units: [0; Faction::MAX],
+        spawners: [0; Faction::MAX],
+        alliances: alliances.clone(),
+    };
+    for force in units.iter() {
+        outcome.units[force.faction.index()] += 1;
+    }
+    for spawner in spawners.iter() {
+        outcome.spawners[spawner.faction.index()] += 1;
+    }
+    *result = HeadlessResult::Finished(outcome);
+    exit.send(AppExit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tests::serial;
+
+    fn outcome(units: &[u32], teams: &[u8]) -> BattleOutcome {
+        let mut outcome = BattleOutcome {
+            seed: 0,
+            seconds: 1.0,
+            units: [0; Faction::MAX],
+            spawners: [0; Faction::MAX],
+            alliances: Alliances::from_teams(teams),
+        };
+        outcome.units[..units.len()].copy_from_slice(units);
+        outcome
+    }
+
+    #[test]
+    fn last_faction_standing_wins() {
+        assert_eq!(
+            outcome(&[0, 3], &[]).winner(),
+            Some("faction 1".to_string())
+        );
+        assert_eq!(outcome(&[2, 3], &[]).winner(), None);
+        assert_eq!(outcome(&[0, 0], &[]).winner(), None);
+    }
+
+    #[test]
+    fn allies_win_together() {
+        let outcome = outcome(&[1, 4, 0, 0], &[0, 0, 1, 1]);
+        assert_eq!(outcome.winner(), Some("team 0".to_string()));
+    }
+
+    #[test]
+    #[serial]
+    fn headless_battle_leaves_its_outcome() {
+        let mut app = App::new();
+        app.insert_resource(CurrentScenario::new("scenarios/skirmish.scenario.ron"))
+            .insert_resource(SimRng::new(3))
+            .add_plugin(Headless { seconds: 2.0 });
+        run_headless(&mut app);
+        match app.world.resource::<HeadlessResult>() {
+            HeadlessResult::Finished(outcome) => {
+                assert_eq!(outcome.seed, 3);
+                assert!(outcome.seconds >= 2.0);
+                assert!(outcome.spawners.iter().sum::<u32>() > 0);
+            }
+            result => panic!("battle did not finish: {:?}", result),
+        }
+    }
+}
@@ -1,12 +1,23 @@
+//! Key combinations handed out to the buttons and players, so each one has its own. A player
+//! can take a whole gamepad from the [GamepadPool] instead. The keys bound to the [Bindings] of
+//! the game are left out of the pool.
+use bevy::input::InputSystem;
 use bevy::prelude::*;
 
+use std::fmt;
+
+use crate::bindings::Bindings;
+
 #[derive(Default)]
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<KeyboardCombinationInput>()
+            .init_resource::<GamepadPool>()
+            .init_resource::<Keyboard>()
             .add_startup_system(test::add_some_input)
+            .add_system_to_stage(CoreStage::PreUpdate, update_keyboard.after(InputSystem))
             .add_system_to_stage(CoreStage::PreUpdate, combination_input_update)
             .add_system_to_stage(CoreStage::PostUpdate, combination_reset_end_frame);
     }
@@ -21,11 +32,14 @@ fn combination_input_update(
         if comb.want_combination && !have_combination {
             let reservation = reserver.reserve();
             if reservation.is_some() {
-                comb.combination = reservation;
+                comb.combination = reservation.map(Combination::Keyboard);
                 comb.change_in_frame = true;
             }
         } else if !comb.want_combination && have_combination {
-            comb.swap_combination(None).map(|x| reserver.liberate(x));
+            // Gamepad buttons go back with their gamepad, not one by one
+            if let Some(Combination::Keyboard(x)) = comb.swap_combination(None) {
+                reserver.liberate(x);
+            }
             comb.change_in_frame = true;
         }
     }
@@ -37,10 +51,28 @@ fn combination_reset_end_frame(mut query: Query<&mut CombinationInput, Changed<C
     }
 }
 
+/// Input bound to a [CombinationInput], from the keyboard or a gamepad.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Combination {
+    Keyboard(KeyboardCombination),
+    Gamepad(GamepadButton),
+}
+
+impl fmt::Display for Combination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Combination::Keyboard(comb) => write!(f, "{}", comb),
+            Combination::Gamepad(button) => {
+                write!(f, "Pad {} {:?}", button.gamepad.id + 1, button.button_type)
+            }
+        }
+    }
+}
+
 #[derive(Default, Debug, Component)]
 pub struct CombinationInput {
     pub want_combination: bool,
-    combination: Option<KeyboardCombination>,
+    combination: Option<Combination>,
     change_in_frame: bool,
 }
 
@@ -53,10 +85,16 @@ impl CombinationInput {
         }
     }
 
-    fn swap_combination(
-        &mut self,
-        new: Option<KeyboardCombination>,
-    ) -> Option<KeyboardCombination> {
+    /// Bound to the combination given instead of one of the pool.
+    pub fn with_combination(combination: Combination) -> Self {
+        CombinationInput {
+            want_combination: true,
+            combination: Some(combination),
+            change_in_frame: true,
+        }
+    }
+
+    fn swap_combination(&mut self, new: Option<Combination>) -> Option<Combination> {
         let old = self.combination.take();
         self.combination = new;
         return old;
@@ -72,95 +110,390 @@ impl ToString for CombinationInput {
     }
 }
 
+/// Hands out the combinations of keys. They are generated as they are reserved: the keys
+/// alone first, then held with a modifier, then sequences of two keys.
 pub struct KeyboardCombinationInput {
-    available: Vec<KeyCode>,
-    given: Vec<KeyCode>,
+    singles: Vec<KeyCode>,
+    modifiers: Vec<KeyCode>,
+    /// Keys of the sequences, they can't be singles or the single would fire on the first key.
+    sequence_keys: Vec<KeyCode>,
+    /// Index of the next combination to generate.
+    next: usize,
+    /// Combinations given back, reused before generating new ones.
+    available: Vec<KeyboardCombination>,
+    given: Vec<KeyboardCombination>,
 }
 
 impl KeyboardCombinationInput {
-    pub fn reserve(&mut self) -> Option<KeyboardCombination> {
-        let available_code = self
-            .available
-            .pop()
-            .map(|x| KeyboardCombination { keycode: x });
-        if let Some(comb) = available_code {
-            self.given.push(comb.keycode);
-            return Some(comb);
-        } else {
-            return None;
+    pub fn new(
+        singles: Vec<KeyCode>,
+        modifiers: Vec<KeyCode>,
+        sequence_keys: Vec<KeyCode>,
+    ) -> Self {
+        KeyboardCombinationInput {
+            singles,
+            modifiers,
+            sequence_keys,
+            next: 0,
+            available: Vec::new(),
+            given: Vec::new(),
+        }
+    }
+
+    /// Combination number `index` of the pool, none past its end.
+    fn generate(&self, index: usize) -> Option<KeyboardCombination> {
+        let singles = self.singles.len();
+        if index < singles {
+            return Some(KeyboardCombination::Single(self.singles[index]));
+        }
+        let index = index - singles;
+        if index < singles * self.modifiers.len() {
+            return Some(KeyboardCombination::Chord(vec![
+                self.modifiers[index / singles],
+                self.singles[index % singles],
+            ]));
+        }
+        let index = index - singles * self.modifiers.len();
+        // Two different keys, the same key twice is too easy to type by mistake
+        let others = self.sequence_keys.len().saturating_sub(1);
+        if index < self.sequence_keys.len() * others {
+            let first = index / others;
+            let mut second = index % others;
+            if second >= first {
+                second += 1;
+            }
+            return Some(KeyboardCombination::Sequence(vec![
+                self.sequence_keys[first],
+                self.sequence_keys[second],
+            ]));
         }
+        None
+    }
+
+    pub fn reserve(&mut self) -> Option<KeyboardCombination> {
+        let comb = match self.available.pop() {
+            Some(comb) => comb,
+            None => {
+                let comb = self.generate(self.next)?;
+                self.next += 1;
+                comb
+            }
+        };
+        self.given.push(comb.clone());
+        Some(comb)
+    }
+
+    /// Pool of the letters, Shift and Control and the digits, except the keys bound to actions.
+    pub fn without(bound: &[KeyCode]) -> Self {
+        let free = |keys: &[KeyCode]| -> Vec<KeyCode> {
+            keys.iter()
+                .filter(|key| !bound.contains(key))
+                .copied()
+                .collect()
+        };
+        KeyboardCombinationInput::new(
+            free(&LETTERS),
+            free(&[KeyCode::LShift, KeyCode::LControl]),
+            free(&SEQUENCE_KEYS),
+        )
+    }
+
+    /// The key is part of the combinations of the pool.
+    pub fn uses(&self, key: KeyCode) -> bool {
+        self.singles.contains(&key)
+            || self.modifiers.contains(&key)
+            || self.sequence_keys.contains(&key)
     }
 
     pub fn liberate(&mut self, comb: KeyboardCombination) {
-        let keycode = comb.keycode;
-        self.available.push(keycode);
-        let index_given = self
-            .given
-            .iter()
-            .enumerate()
-            .find(|(_i, x)| x == &&keycode)
-            .unwrap()
-            .0;
-        self.given.swap_remove(index_given);
+        if let Some(index_given) = self.given.iter().position(|x| *x == comb) {
+            self.given.swap_remove(index_given);
+        }
+        self.available.push(comb);
     }
 }
 
 impl Default for KeyboardCombinationInput {
     fn default() -> Self {
-        KeyboardCombinationInput {
-            available: vec![KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E],
-            given: Vec::new(),
-        }
+        KeyboardCombinationInput::without(&Bindings::default().keys())
     }
 }
 
-#[derive(Debug)]
-pub struct KeyboardCombination {
-    keycode: KeyCode,
+const LETTERS: [KeyCode; 26] = [
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+];
+
+/// Keys a single key combination doesn't fire with.
+const MODIFIERS: [KeyCode; 6] = [
+    KeyCode::LShift,
+    KeyCode::RShift,
+    KeyCode::LControl,
+    KeyCode::RControl,
+    KeyCode::LAlt,
+    KeyCode::RAlt,
+];
+
+const SEQUENCE_KEYS: [KeyCode; 10] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Key0,
+];
+
+const SEQUENCE_LENGTH: usize = 2;
+
+/// Seconds to type the next key of a sequence.
+const SEQUENCE_TIMEOUT: f32 = 1.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyboardCombination {
+    /// The key alone, without any modifier held.
+    Single(KeyCode),
+    /// Keys held together, like Shift+Q.
+    Chord(Vec<KeyCode>),
+    /// Keys typed one after the other, like 1 then 2.
+    Sequence(Vec<KeyCode>),
 }
 
-impl ToString for KeyboardCombination {
-    fn to_string(&self) -> String {
-        format!("{:?}", self.keycode)
+fn key_name(key: KeyCode) -> String {
+    let name = format!("{:?}", key);
+    match name.strip_prefix("Key") {
+        Some(digit) if !digit.is_empty() => digit.to_string(),
+        _ => name,
+    }
+}
+
+impl fmt::Display for KeyboardCombination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (keys, separator) = match self {
+            KeyboardCombination::Single(key) => return write!(f, "{}", key_name(*key)),
+            KeyboardCombination::Chord(keys) => (keys, "+"),
+            KeyboardCombination::Sequence(keys) => (keys, " "),
+        };
+        let names: Vec<_> = keys.iter().map(|key| key_name(*key)).collect();
+        write!(f, "{}", names.join(separator))
+    }
+}
+
+/// State of the keyboard kept across frames, to recognize the sequences of keys.
+#[derive(Default)]
+pub struct Keyboard {
+    input: Input<KeyCode>,
+    time: f32,
+    /// Keys of the sequence being typed.
+    typing: Vec<KeyCode>,
+    last_typed: f32,
+    /// Sequence typed this frame.
+    completed: Option<Vec<KeyCode>>,
+    /// Last sequence typed, while its last key is held.
+    held: Option<Vec<KeyCode>>,
+    /// Sequence whose last key was released this frame.
+    released: Option<Vec<KeyCode>>,
+}
+
+impl Keyboard {
+    /// Take the keys of a new frame, `delta` seconds after the previous one.
+    pub fn update(&mut self, input: &Input<KeyCode>, delta: f32) {
+        self.input = input.clone();
+        self.time += delta;
+        self.completed = None;
+        self.released = None;
+        let last_held = self.held.as_ref().and_then(|held| held.last().cloned());
+        if let Some(key) = last_held {
+            if !input.pressed(key) {
+                self.released = self.held.take();
+            }
+        }
+        if self.time - self.last_typed > SEQUENCE_TIMEOUT {
+            self.typing.clear();
+        }
+        for key in input.get_just_pressed() {
+            if !SEQUENCE_KEYS.contains(key) {
+                continue;
+            }
+            self.typing.push(*key);
+            self.last_typed = self.time;
+            if self.typing.len() == SEQUENCE_LENGTH {
+                let sequence = std::mem::take(&mut self.typing);
+                self.held = Some(sequence.clone());
+                self.completed = Some(sequence);
+            }
+        }
+    }
+
+    fn modifier_held(&self) -> bool {
+        MODIFIERS.iter().any(|key| self.input.pressed(*key))
     }
 }
 
+pub fn update_keyboard(
+    input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut keyboard: ResMut<Keyboard>,
+) {
+    keyboard.update(&input, time.delta_seconds());
+}
+
 pub trait InputTrait<T> {
     fn pressed_t(&self, elem: &T) -> bool;
     fn just_pressed_t(&self, elem: &T) -> bool;
     fn just_released_t(&self, elem: &T) -> bool;
 }
 
-impl InputTrait<KeyboardCombination> for Input<KeyCode> {
+impl InputTrait<KeyboardCombination> for Keyboard {
     fn pressed_t(&self, comb: &KeyboardCombination) -> bool {
-        self.pressed(comb.keycode)
+        match comb {
+            KeyboardCombination::Single(key) => self.input.pressed(*key) && !self.modifier_held(),
+            KeyboardCombination::Chord(keys) => keys.iter().all(|key| self.input.pressed(*key)),
+            KeyboardCombination::Sequence(keys) => self.held.as_ref() == Some(keys),
+        }
     }
     fn just_pressed_t(&self, comb: &KeyboardCombination) -> bool {
-        self.just_pressed(comb.keycode)
+        match comb {
+            KeyboardCombination::Single(key) => {
+                self.input.just_pressed(*key) && !self.modifier_held()
+            }
+            // Whichever key comes last completes the chord
+            KeyboardCombination::Chord(keys) => {
+                self.pressed_t(comb) && keys.iter().any(|key| self.input.just_pressed(*key))
+            }
+            KeyboardCombination::Sequence(keys) => self.completed.as_ref() == Some(keys),
+        }
     }
     fn just_released_t(&self, comb: &KeyboardCombination) -> bool {
-        self.just_released(comb.keycode)
+        match comb {
+            KeyboardCombination::Single(key) => self.input.just_released(*key),
+            KeyboardCombination::Chord(keys) => {
+                keys.iter().any(|key| self.input.just_released(*key))
+                    && keys
+                        .iter()
+                        .all(|key| self.input.pressed(*key) || self.input.just_released(*key))
+            }
+            KeyboardCombination::Sequence(keys) => self.released.as_ref() == Some(keys),
+        }
     }
 }
 
-impl InputTrait<CombinationInput> for Input<KeyCode> {
+impl InputTrait<CombinationInput> for Keyboard {
     fn pressed_t(&self, comb: &CombinationInput) -> bool {
-        comb.combination
-            .as_ref()
-            .map(|x| InputTrait::pressed_t(self, x))
-            .unwrap_or(false)
+        match &comb.combination {
+            Some(Combination::Keyboard(x)) => InputTrait::pressed_t(self, x),
+            _ => false,
+        }
     }
     fn just_pressed_t(&self, comb: &CombinationInput) -> bool {
-        comb.combination
-            .as_ref()
-            .map(|x| InputTrait::just_pressed_t(self, x))
-            .unwrap_or(false)
+        match &comb.combination {
+            Some(Combination::Keyboard(x)) => InputTrait::just_pressed_t(self, x),
+            _ => false,
+        }
     }
     fn just_released_t(&self, comb: &CombinationInput) -> bool {
-        comb.combination
-            .as_ref()
-            .map(|x| InputTrait::just_released_t(self, x))
-            .unwrap_or(comb.change_in_frame)
+        match &comb.combination {
+            Some(Combination::Keyboard(x)) => InputTrait::just_released_t(self, x),
+            Some(Combination::Gamepad(_)) => false,
+            None => comb.change_in_frame,
+        }
+    }
+}
+
+impl InputTrait<GamepadButton> for Input<GamepadButton> {
+    fn pressed_t(&self, button: &GamepadButton) -> bool {
+        self.pressed(*button)
+    }
+    fn just_pressed_t(&self, button: &GamepadButton) -> bool {
+        self.just_pressed(*button)
+    }
+    fn just_released_t(&self, button: &GamepadButton) -> bool {
+        self.just_released(*button)
+    }
+}
+
+impl InputTrait<CombinationInput> for Input<GamepadButton> {
+    fn pressed_t(&self, comb: &CombinationInput) -> bool {
+        match &comb.combination {
+            Some(Combination::Gamepad(x)) => InputTrait::pressed_t(self, x),
+            _ => false,
+        }
+    }
+    fn just_pressed_t(&self, comb: &CombinationInput) -> bool {
+        match &comb.combination {
+            Some(Combination::Gamepad(x)) => InputTrait::just_pressed_t(self, x),
+            _ => false,
+        }
+    }
+    fn just_released_t(&self, comb: &CombinationInput) -> bool {
+        match &comb.combination {
+            Some(Combination::Gamepad(x)) => InputTrait::just_released_t(self, x),
+            Some(Combination::Keyboard(_)) => false,
+            None => comb.change_in_frame,
+        }
+    }
+}
+
+/// Hands out whole gamepads, each one goes to a single player.
+#[derive(Default)]
+pub struct GamepadPool {
+    given: Vec<Gamepad>,
+}
+
+impl GamepadPool {
+    /// Gamepad nobody has yet whose `button` was just pressed.
+    pub fn just_pressed_free(
+        &self,
+        input: &Input<GamepadButton>,
+        button: GamepadButtonType,
+    ) -> Option<Gamepad> {
+        input
+            .get_just_pressed()
+            .filter(|pressed| pressed.button_type == button)
+            .map(|pressed| pressed.gamepad)
+            .find(|gamepad| !self.given.contains(gamepad))
+    }
+
+    pub fn reserve(&mut self, gamepad: Gamepad) -> Option<Gamepad> {
+        if self.given.contains(&gamepad) {
+            return None;
+        }
+        self.given.push(gamepad);
+        Some(gamepad)
+    }
+
+    /// The gamepad can be taken again, once its player left or it was disconnected.
+    pub fn liberate(&mut self, gamepad: Gamepad) {
+        if let Some(index_given) = self.given.iter().position(|x| *x == gamepad) {
+            self.given.swap_remove(index_given);
+        }
     }
 }
 
@@ -184,3 +517,113 @@ mod test {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_goes_on_with_chords_then_sequences() {
+        let mut pool = KeyboardCombinationInput::new(
+            vec![KeyCode::A, KeyCode::B],
+            vec![KeyCode::LShift],
+            vec![KeyCode::Key1, KeyCode::Key2],
+        );
+        let reserved: Vec<_> = std::iter::from_fn(|| pool.reserve())
+            .map(|comb| comb.to_string())
+            .collect();
+        assert_eq!(reserved, ["A", "B", "LShift+A", "LShift+B", "1 2", "2 1"]);
+
+        pool.liberate(KeyboardCombination::Single(KeyCode::B));
+        assert_eq!(
+            pool.reserve(),
+            Some(KeyboardCombination::Single(KeyCode::B))
+        );
+        assert_eq!(pool.reserve(), None);
+    }
+
+    #[test]
+    fn gamepad_is_given_to_a_single_player() {
+        let mut input = Input::default();
+        let mut pool = GamepadPool::default();
+        let start = |id| GamepadButton::new(Gamepad::new(id), GamepadButtonType::Start);
+        input.press(start(0));
+        input.press(start(1));
+        let first = pool.just_pressed_free(&input, GamepadButtonType::Start);
+        assert!(first.is_some());
+        assert_eq!(pool.reserve(first.unwrap()), first);
+        assert_eq!(pool.reserve(first.unwrap()), None);
+        let second = pool.just_pressed_free(&input, GamepadButtonType::Start);
+        assert!(second.is_some() && second != first);
+        assert_eq!(
+            pool.just_pressed_free(&input, GamepadButtonType::South),
+            None
+        );
+
+        pool.liberate(first.unwrap());
+        assert_eq!(pool.reserve(first.unwrap()), first);
+    }
+
+    #[test]
+    fn combination_is_read_from_its_device() {
+        let mut pads = Input::default();
+        let keyboard = Keyboard::default();
+        let south = |id| GamepadButton::new(Gamepad::new(id), GamepadButtonType::South);
+        let comb = CombinationInput::with_combination(Combination::Gamepad(south(1)));
+        pads.press(south(0));
+        assert!(!pads.just_pressed_t(&comb));
+        pads.press(south(1));
+        assert!(pads.just_pressed_t(&comb));
+        assert!(!keyboard.just_pressed_t(&comb));
+        assert_eq!(comb.to_string(), "Pad 2 South");
+    }
+
+    #[test]
+    fn chord_hides_the_single_key() {
+        let mut input = Input::default();
+        let mut keyboard = Keyboard::default();
+        let single = KeyboardCombination::Single(KeyCode::Q);
+        let chord = KeyboardCombination::Chord(vec![KeyCode::LShift, KeyCode::Q]);
+        input.press(KeyCode::LShift);
+        keyboard.update(&input, 0.1);
+        assert!(!keyboard.just_pressed_t(&chord));
+
+        input.clear();
+        input.press(KeyCode::Q);
+        keyboard.update(&input, 0.1);
+        assert!(keyboard.just_pressed_t(&chord));
+        assert!(!keyboard.just_pressed_t(&single));
+
+        input.clear();
+        input.release(KeyCode::LShift);
+        keyboard.update(&input, 0.1);
+        assert!(keyboard.just_released_t(&chord));
+        assert!(keyboard.pressed_t(&single));
+    }
+
+    #[test]
+    fn sequence_is_typed_across_frames() {
+        let mut input = Input::default();
+        let mut keyboard = Keyboard::default();
+        let sequence = KeyboardCombination::Sequence(vec![KeyCode::Key1, KeyCode::Key2]);
+        let mut type_key = |keyboard: &mut Keyboard, key, delta| {
+            input.clear();
+            input.release(KeyCode::Key1);
+            input.release(KeyCode::Key2);
+            input.press(key);
+            keyboard.update(&input, delta);
+        };
+
+        type_key(&mut keyboard, KeyCode::Key1, 0.1);
+        assert!(!keyboard.just_pressed_t(&sequence));
+        type_key(&mut keyboard, KeyCode::Key2, 0.5);
+        assert!(keyboard.just_pressed_t(&sequence));
+        assert!(keyboard.pressed_t(&sequence));
+
+        // Too slow, the 1 is forgotten
+        type_key(&mut keyboard, KeyCode::Key1, 0.1);
+        assert!(keyboard.just_released_t(&sequence));
+        type_key(&mut keyboard, KeyCode::Key2, 2.0);
+        assert!(!keyboard.just_pressed_t(&sequence));
+    }
+}
@@ -1,9 +1,152 @@
 //! # Multi warrior documentation
 //! You will find more documentation in the [multi_warrior_lib]
+//!
+//! ```text
+//! multi_warrior [--scenario PATH] [--seed N] [--record PATH | --replay PATH]
+//!               [--headless [--seconds N]]
+//! ```
 
+use bevy::log::{Level, LogPlugin, LogSettings};
 use bevy::prelude::*;
-use multi_warrior_lib::Game;
+use multi_warrior_lib::{
+    run_headless, CurrentScenario, Game, Headless, HeadlessResult, Replay, ReplayMode, SimRng,
+};
+
+use std::path::Path;
+use std::process::exit;
+
+const USAGE: &str =
+    "Usage: multi_warrior [--scenario PATH] [--seed N] [--record PATH | --replay PATH]
+                     [--headless [--seconds N]]
+
+Options:
+    --scenario PATH  Scenario to play, relative to the assets folder
+    --seed N         Seed of the battle, the same seed replays the same battle
+    --record PATH    Save the battle to a replay file when the app exits
+    --replay PATH    Play a replay file, the scenario and seed come from it
+    --headless       Simulate the battle without window and print the outcome
+    --seconds N      Length of the headless battle (default 60, or the whole replay)
+    --help           Print this message";
+
+struct Args {
+    scenario: Option<String>,
+    seed: Option<u64>,
+    record: Option<String>,
+    replay: Option<String>,
+    headless: bool,
+    seconds: Option<f32>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Args {
+            scenario: None,
+            seed: None,
+            record: None,
+            replay: None,
+            headless: false,
+            seconds: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => parsed.headless = true,
+                "--scenario" => {
+                    parsed.scenario = Some(args.next().ok_or("--scenario needs a path")?);
+                }
+                "--seed" => {
+                    let value = args.next().ok_or("--seed needs a number")?;
+                    parsed.seed = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid seed: {}", value))?,
+                    );
+                }
+                "--record" => {
+                    parsed.record = Some(args.next().ok_or("--record needs a path")?);
+                }
+                "--replay" => {
+                    parsed.replay = Some(args.next().ok_or("--replay needs a path")?);
+                }
+                "--seconds" => {
+                    let value = args.next().ok_or("--seconds needs a number")?;
+                    parsed.seconds = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid number of seconds: {}", value))?,
+                    );
+                }
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    exit(0);
+                }
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+        if parsed.record.is_some() && parsed.replay.is_some() {
+            return Err("--record and --replay can't be used together".to_string());
+        }
+        Ok(parsed)
+    }
+}
 
 fn main() {
-    App::new().add_plugin(Game).run();
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        exit(2);
+    });
+
+    let mut app = App::new();
+    let mut seconds = args.seconds.unwrap_or(60.0);
+    if let Some(scenario) = args.scenario {
+        app.insert_resource(CurrentScenario::new(scenario));
+    }
+    if let Some(seed) = args.seed {
+        app.insert_resource(SimRng::new(seed));
+    }
+    if let Some(path) = args.record {
+        app.insert_resource(ReplayMode::record(path));
+    }
+    if let Some(path) = args.replay {
+        let replay = Replay::load(Path::new(&path)).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(1);
+        });
+        seconds = args.seconds.unwrap_or_else(|| replay.duration());
+        app.insert_resource(CurrentScenario::new(replay.scenario.clone()))
+            .insert_resource(SimRng::new(replay.seed))
+            .insert_resource(ReplayMode::play(replay));
+    }
+
+    if !args.headless {
+        app.add_plugin(Game).run();
+        return;
+    }
+
+    app.insert_resource(LogSettings {
+        level: Level::WARN,
+        ..Default::default()
+    })
+    .add_plugin(LogPlugin)
+    .add_plugin(Headless { seconds });
+    run_headless(&mut app);
+    match app.world.resource::<HeadlessResult>() {
+        HeadlessResult::Finished(outcome) => println!("{}", outcome),
+        HeadlessResult::Failed(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+        HeadlessResult::Running => {
+            eprintln!("battle interrupted");
+            exit(1);
+        }
+    }
+    if let Some(player) = app.world.resource::<ReplayMode>().player() {
+        match player.diverged_at() {
+            Some(tick) => {
+                eprintln!("Replay diverged at tick {}", tick);
+                exit(1);
+            }
+            None => println!("Replay matched"),
+        }
+    }
 }
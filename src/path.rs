@@ -0,0 +1,281 @@
+use bevy::prelude::*;
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+
+use crate::grid::*;
+use crate::utils::{Direction, *};
+
+/// Path cached on a unit so it doesn't need to search the grid on every step.
+#[derive(Default, Component)]
+pub struct UnitPath {
+    target: Option<(i32, i32)>,
+    cells: VecDeque<(i32, i32)>,
+    reachable: bool,
+}
+
+impl UnitPath {
+    pub fn invalidate(&mut self) {
+        self.target = None;
+        self.cells.clear();
+        self.reachable = false;
+    }
+
+    /// False when the last search did not find any way to the target.
+    pub fn is_reachable(&self) -> bool {
+        self.reachable
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.cells.contains(&(x, y))
+    }
+
+    fn starts_next_to(&self, x: i32, y: i32) -> bool {
+        self.cells
+            .front()
+            .map(|(n_x, n_y)| (n_x - x).abs() + (n_y - y).abs() == 1)
+            .unwrap_or(false)
+    }
+
+    fn compute(
+        &mut self,
+        grid: &Grid,
+        x: i32,
+        y: i32,
+        target_x: i32,
+        target_y: i32,
+        status_wanted: GridStatus,
+    ) {
+        let path = find_path(grid, (x, y), (target_x, target_y), status_wanted);
+        self.target = Some((target_x, target_y));
+        self.reachable = path.is_some();
+        self.cells = path.unwrap_or_default();
+    }
+
+    /// Take the next cell of the path to the target, searching a new path if the cached one is
+    /// stale. None means the unit should wait, either because it arrived or because the next cell
+    /// is held by someone it can't walk through.
+    pub fn next_step(
+        &mut self,
+        grid: &Grid,
+        x: i32,
+        y: i32,
+        target_x: i32,
+        target_y: i32,
+        status_wanted: GridStatus,
+    ) -> Option<(Direction, i32, i32)> {
+        if self.target != Some((target_x, target_y)) || !self.starts_next_to(x, y) {
+            self.compute(grid, x, y, target_x, target_y, status_wanted);
+        }
+
+        let &(next_x, next_y) = self.cells.front()?;
+        if !is_passable(grid, next_x, next_y, status_wanted) {
+            if (next_x, next_y) == (target_x, target_y) {
+                return None;
+            }
+            self.compute(grid, x, y, target_x, target_y, status_wanted);
+            let &(next_x, next_y) = self.cells.front()?;
+            if !is_passable(grid, next_x, next_y, status_wanted) {
+                return None;
+            }
+        }
+
+        let (next_x, next_y) = self.cells.pop_front()?;
+        Direction::iter()
+            .find(|d| d.x() == next_x - x && d.y() == next_y - y)
+            .map(|d| (d, next_x, next_y))
+    }
+}
+
+pub fn is_passable(grid: &Grid, x: i32, y: i32, status_wanted: GridStatus) -> bool {
+    grid.is_walkable(x, y)
+        && grid
+            .get_status(x, y)
+            .map(|status| status == status_wanted || status == GridStatus::Neutral)
+            .unwrap_or(false)
+}
+
+#[derive(PartialEq, Eq)]
+struct PathNode {
+    cost: i32,
+    heuristic: i32,
+    pos: usize,
+}
+
+impl Ord for PathNode {
+    // Reversed so the BinaryHeap pops the cheapest node first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.cost + other.heuristic)
+            .cmp(&(self.cost + self.heuristic))
+            .then_with(|| other.heuristic.cmp(&self.heuristic))
+            .then_with(|| other.pos.cmp(&self.pos))
+    }
+}
+
+impl PartialOrd for PathNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A* search from `from` to `to` over the cells passable for `status_wanted`, weighted by the
+/// terrain movement cost.
+///
+/// The returned path excludes the start and includes the target. The target itself doesn't
+/// need to be passable so units can path next to an enemy they want to attack.
+pub fn find_path(
+    grid: &Grid,
+    from: (i32, i32),
+    to: (i32, i32),
+    status_wanted: GridStatus,
+) -> Option<VecDeque<(i32, i32)>> {
+    let start = grid.to_pos(from.0, from.1)?;
+    let goal = grid.to_pos(to.0, to.1)?;
+    let to_xy = |pos: usize| (pos as i32 / grid.y, pos as i32 % grid.y);
+    let heuristic = |(x, y): (i32, i32)| (to.0 - x).abs() + (to.1 - y).abs();
+
+    let cell_count = (grid.x * grid.y) as usize;
+    let mut best_cost = vec![i32::MAX; cell_count];
+    let mut came_from = vec![usize::MAX; cell_count];
+    let mut open = BinaryHeap::new();
+
+    best_cost[start] = 0;
+    open.push(PathNode {
+        cost: 0,
+        heuristic: heuristic(from),
+        pos: start,
+    });
+
+    while let Some(PathNode { cost, pos, .. }) = open.pop() {
+        if pos == goal {
+            let mut path = VecDeque::new();
+            let mut current = goal;
+            while current != start {
+                path.push_front(to_xy(current));
+                current = came_from[current];
+            }
+            return Some(path);
+        }
+        if cost > best_cost[pos] {
+            continue;
+        }
+
+        let (x, y) = to_xy(pos);
+        for d in Direction::iter() {
+            let (n_x, n_y) = (x + d.x(), y + d.y());
+            let n_pos = match grid.to_pos(n_x, n_y) {
+                Some(n_pos) => n_pos,
+                None => continue,
+            };
+            if n_pos != goal && !is_passable(grid, n_x, n_y, status_wanted) {
+                continue;
+            }
+            let n_cost = cost + grid.movement_cost(n_x, n_y).unwrap_or(1);
+            if n_cost < best_cost[n_pos] {
+                best_cost[n_pos] = n_cost;
+                came_from[n_pos] = pos;
+                open.push(PathNode {
+                    cost: n_cost,
+                    heuristic: heuristic((n_x, n_y)),
+                    pos: n_pos,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// Drop the cached paths going through a cell that changed during the frame.
+pub fn invalidate_unit_paths(mut grid: ResMut<Grid>, mut query: Query<&mut UnitPath>) {
+    let changed = grid.take_changed_cells();
+    if changed.is_empty() {
+        return;
+    }
+    for mut path in query.iter_mut() {
+        if changed.iter().any(|(x, y)| path.contains(*x, *y)) {
+            path.invalidate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::faction::*;
+
+    #[test]
+    fn path_on_empty_grid_is_shortest() {
+        let grid = Grid::new(5, 5);
+        let path = find_path(&grid, (0, 0), (4, 3), GridStatus::Held(Faction(0))).unwrap();
+        assert_eq!(path.len(), 7);
+        assert_eq!(path.back(), Some(&(4, 3)));
+    }
+
+    #[test]
+    fn path_goes_around_enemies() {
+        // Wall of enemies on x = 1 with an opening at the top
+        let mut grid = Grid::new(3, 4);
+        for y in 0..3 {
+            grid.add_unit(1, y, Faction(1));
+        }
+        let path = find_path(&grid, (0, 0), (2, 0), GridStatus::Held(Faction(0))).unwrap();
+        assert!(path.contains(&(1, 3)));
+        assert!(path
+            .iter()
+            .all(|(x, y)| is_passable(&grid, *x, *y, GridStatus::Held(Faction(0)))));
+    }
+
+    #[test]
+    fn path_prefers_cheap_terrain() {
+        let mut grid = Grid::new(4, 2);
+        grid.set_terrain(1, 0, Terrain::Water);
+        grid.set_terrain(2, 0, Terrain::Water);
+        let path = find_path(&grid, (0, 0), (3, 0), GridStatus::Held(Faction(0))).unwrap();
+        assert_eq!(
+            path,
+            VecDeque::from(vec![(0, 1), (1, 1), (2, 1), (3, 1), (3, 0)])
+        );
+
+        grid.set_terrain(1, 1, Terrain::Wall);
+        let path = find_path(&grid, (0, 0), (3, 0), GridStatus::Held(Faction(0))).unwrap();
+        assert_eq!(path, VecDeque::from(vec![(1, 0), (2, 0), (3, 0)]));
+    }
+
+    #[test]
+    fn no_path_when_walled_off() {
+        let mut grid = Grid::new(3, 3);
+        for y in 0..3 {
+            grid.add_unit(1, y, Faction(1));
+        }
+        assert!(find_path(&grid, (0, 0), (2, 2), GridStatus::Held(Faction(0))).is_none());
+    }
+
+    #[test]
+    fn target_can_be_held_by_enemy() {
+        let mut grid = Grid::new(3, 1);
+        grid.add_unit(2, 0, Faction(1));
+        let path = find_path(&grid, (0, 0), (2, 0), GridStatus::Neutral).unwrap();
+        assert_eq!(path, VecDeque::from(vec![(1, 0), (2, 0)]));
+
+        let mut unit_path = UnitPath::default();
+        assert!(unit_path
+            .next_step(&grid, 0, 0, 2, 0, GridStatus::Neutral)
+            .is_some());
+        assert!(unit_path
+            .next_step(&grid, 1, 0, 2, 0, GridStatus::Neutral)
+            .is_none());
+    }
+
+    #[test]
+    fn path_is_invalidated_when_grid_changes_on_it() {
+        let mut grid = Grid::new(4, 1);
+        let mut unit_path = UnitPath::default();
+        unit_path.next_step(&grid, 0, 0, 3, 0, GridStatus::Held(Faction(0)));
+        grid.take_changed_cells();
+
+        grid.add_unit(2, 0, Faction(1));
+        let changed = grid.take_changed_cells();
+        assert!(changed.iter().any(|(x, y)| unit_path.contains(*x, *y)));
+    }
+}
@@ -0,0 +1,172 @@
+//! Cell of the grid under the cursor, shared by everything pointing at the battle.
+//!
+//! The cursor goes through the projection of the [MainCamera] whose viewport it is in to the
+//! world, then to the grid with [GridRenderDebug::cell_at]. [CursorCell] keeps the result of the
+//! frame, and the hovered cell is highlighted.
+use bevy::prelude::*;
+
+use crate::camera::MainCamera;
+use crate::grid::*;
+
+pub struct PickingPlugin;
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CursorCell>()
+            .add_startup_system(spawn_hover_node)
+            .add_system_to_stage(CoreStage::PreUpdate, update_cursor_cell)
+            .add_system(update_hover_node);
+    }
+}
+
+/// Where the cursor points this frame, none while it is out of the window or the grid.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CursorCell {
+    /// Position in the window, from the bottom left corner.
+    pub screen: Option<Vec2>,
+    /// Main camera of the view under the cursor.
+    pub camera: Option<Entity>,
+    pub world: Option<Vec2>,
+    pub cell: Option<(i32, i32)>,
+}
+
+/// Part of the window drawn by a camera, as its bottom left corner and size in the coordinates
+/// of the cursor.
+pub fn viewport_rect(camera: &Camera, window: &Window) -> (Vec2, Vec2) {
+    match &camera.viewport {
+        Some(viewport) => {
+            let scale = window.scale_factor() as f32;
+            let top_left = viewport.physical_position.as_vec2() / scale;
+            let size = viewport.physical_size.as_vec2() / scale;
+            (
+                Vec2::new(top_left.x, window.height() - top_left.y - size.y),
+                size,
+            )
+        }
+        None => (Vec2::ZERO, Vec2::new(window.width(), window.height())),
+    }
+}
+
+/// Point of the world shown at `screen`, a position in the window from its bottom left corner.
+pub fn screen_to_world(
+    screen: Vec2,
+    window: &Window,
+    camera: &Camera,
+    transform: &GlobalTransform,
+) -> Vec2 {
+    let (min, size) = viewport_rect(camera, window);
+    let ndc_to_world = transform.compute_matrix() * camera.projection_matrix().inverse();
+    unproject(screen - min, size, ndc_to_world)
+}
+
+fn unproject(screen: Vec2, size: Vec2, ndc_to_world: Mat4) -> Vec2 {
+    let ndc = (screen / size) * 2.0 - Vec2::ONE;
+    ndc_to_world.project_point3(ndc.extend(-1.0)).truncate()
+}
+
+/// Cell of the grid at a point of the world, none out of the grid.
+pub fn world_to_cell(grid: &Grid, grid_debug: &GridRenderDebug, world: Vec2) -> Option<(i32, i32)> {
+    let (x, y) = grid_debug.cell_at(world);
+    grid.to_pos(x, y).map(|_| (x, y))
+}
+
+fn update_cursor_cell(
+    windows: Res<Windows>,
+    grid: Res<Grid>,
+    grid_debug: Res<GridRenderDebug>,
+    mut cursor: ResMut<CursorCell>,
+    cameras: Query<(Entity, &Camera, &GlobalTransform), With<MainCamera>>,
+) {
+    let window = windows.get_primary();
+    let screen = window.and_then(|window| window.cursor_position());
+    let (camera, world) = match (window, screen) {
+        (Some(window), Some(screen)) => cameras
+            .iter()
+            .find(|(_, camera, _)| {
+                let (min, size) = viewport_rect(camera, window);
+                screen.cmpge(min).all() && screen.cmplt(min + size).all()
+            })
+            .map(|(entity, camera, transform)| {
+                (
+                    Some(entity),
+                    Some(screen_to_world(screen, window, camera, transform)),
+                )
+            })
+            .unwrap_or((None, None)),
+        _ => (None, None),
+    };
+    let cell = world.and_then(|world| world_to_cell(&grid, &grid_debug, world));
+    let picked = CursorCell {
+        screen,
+        camera,
+        world,
+        cell,
+    };
+    // Only changed when the cursor moves, for the systems watching it
+    if *cursor != picked {
+        *cursor = picked;
+    }
+}
+
+#[derive(Component)]
+struct HoverNode;
+
+// Over the territory, behind the units
+const HOVER_NODE_Z: f32 = -0.07;
+
+fn spawn_hover_node(mut commands: Commands) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.25),
+                ..Default::default()
+            },
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(HoverNode)
+        .insert(GridLayer(HOVER_NODE_Z))
+        .insert(GridTransform::on(0, 0));
+}
+
+fn update_hover_node(
+    cursor: Res<CursorCell>,
+    mut nodes: Query<(&mut GridTransform, &mut Visibility), With<HoverNode>>,
+) {
+    if !cursor.is_changed() {
+        return;
+    }
+    for (mut node, mut visibility) in nodes.iter_mut() {
+        if let Some((x, y)) = cursor.cell {
+            node.x = x as f32;
+            node.y = y as f32;
+        }
+        visibility.is_visible = cursor.cell.is_some();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_points_go_through_the_camera() {
+        let size = Vec2::new(800.0, 600.0);
+        let projection = Mat4::orthographic_rh(-400.0, 400.0, -300.0, 300.0, 0.0, 1000.0);
+        let camera = Mat4::from_translation(Vec3::new(50.0, -20.0, 999.0));
+        let ndc_to_world = camera * projection.inverse();
+        let close = |a: Vec2, b: Vec2| a.distance(b) < 0.01;
+        assert!(close(
+            unproject(size / 2.0, size, ndc_to_world),
+            Vec2::new(50.0, -20.0)
+        ));
+        assert!(close(
+            unproject(Vec2::ZERO, size, ndc_to_world),
+            Vec2::new(-350.0, -320.0)
+        ));
+        assert!(close(
+            unproject(Vec2::new(800.0, 450.0), size, ndc_to_world),
+            Vec2::new(450.0, 130.0)
+        ));
+    }
+}
@@ -0,0 +1,440 @@
+//! Local players sharing the keyboard and the gamepads.
+//!
+//! The key of [Action::Join] makes a new player join, it takes the first faction of the scenario
+//! free and a combination of [KeyboardCombinationInput] for each of its [PlayerAction]s. Start
+//! on a free gamepad makes a player join with the whole gamepad instead, taken from the
+//! [GamepadPool]. Select makes it leave, like disconnecting the gamepad, which goes back to the
+//! pool. The actions are sent as [PlayerCommand]s, so they are recorded in replays like any other
+//! order.
+use bevy::prelude::*;
+
+use crate::bindings::*;
+use crate::button::ButtonSpawner;
+use crate::command::*;
+use crate::faction::*;
+use crate::input::*;
+use crate::scenario::*;
+use crate::sim::*;
+use crate::spawn::SpawnInfo;
+use crate::state::AppState;
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Players>()
+            .add_startup_system(spawn_player_hud)
+            .add_system(join_players)
+            .add_system(leave_players)
+            .add_system(update_player_hud)
+            .add_system_set(SystemSet::on_update(AppState::Playing).with_system(player_actions));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerAction {
+    /// Rally the units of the force on the next spawner, the enemy ones first.
+    Rally,
+    /// Call the next unit of every spawner now, or buy the cheapest unit with an economy.
+    Spawn,
+    /// The units at the rally point charge the nearest enemies.
+    Charge,
+}
+
+impl PlayerAction {
+    pub const ALL: [PlayerAction; 3] = [
+        PlayerAction::Rally,
+        PlayerAction::Spawn,
+        PlayerAction::Charge,
+    ];
+
+    /// Seconds of battle before the action can be used again.
+    pub fn cooldown(&self) -> f32 {
+        match self {
+            PlayerAction::Rally => 0.0,
+            PlayerAction::Spawn => 3.0,
+            PlayerAction::Charge => 15.0,
+        }
+    }
+
+    /// Button of the action for the players with a gamepad.
+    pub fn gamepad_button(&self) -> GamepadButtonType {
+        match self {
+            PlayerAction::Rally => GamepadButtonType::South,
+            PlayerAction::Spawn => GamepadButtonType::West,
+            PlayerAction::Charge => GamepadButtonType::East,
+        }
+    }
+}
+
+pub struct Player {
+    pub faction: Faction,
+    /// Gamepad of the player, it uses the keyboard without one.
+    pub gamepad: Option<Gamepad>,
+    /// Spawner of the next rally.
+    rally: usize,
+    /// Time of the battle when each action is ready again.
+    ready_at: [f32; PlayerAction::ALL.len()],
+}
+
+#[derive(Default)]
+pub struct Players {
+    list: Vec<Player>,
+}
+
+impl Players {
+    /// Add a player on the first of the `factions` free, if there is one.
+    pub fn join(&mut self, gamepad: Option<Gamepad>, factions: &[Faction]) -> Option<usize> {
+        let faction = *factions
+            .iter()
+            .find(|faction| self.list.iter().all(|p| p.faction != **faction))?;
+        self.list.push(Player {
+            faction,
+            gamepad,
+            rally: 0,
+            ready_at: [0.0; PlayerAction::ALL.len()],
+        });
+        Some(self.list.len() - 1)
+    }
+
+    pub fn get(&self, player: usize) -> Option<&Player> {
+        self.list.get(player)
+    }
+
+    /// Remove the player, the ones after it move down a place.
+    pub fn leave(&mut self, player: usize) -> Option<Player> {
+        (player < self.list.len()).then(|| self.list.remove(player))
+    }
+
+    /// Player with the gamepad.
+    pub fn with_gamepad(&self, gamepad: Gamepad) -> Option<usize> {
+        self.list
+            .iter()
+            .position(|player| player.gamepad == Some(gamepad))
+    }
+
+    /// Number of players joined.
+    pub fn count(&self) -> usize {
+        self.list.len()
+    }
+
+    /// First player on the keyboard, the mouse is its own.
+    pub fn mouse_player(&self) -> Option<usize> {
+        self.list.iter().position(|player| player.gamepad.is_none())
+    }
+
+    /// Faction commanded with the mouse: the one of the first player on the keyboard, or the
+    /// first faction while nobody joined.
+    pub fn mouse_faction(&self) -> Faction {
+        self.mouse_player()
+            .map(|player| self.list[player].faction)
+            .unwrap_or(Faction(0))
+    }
+
+    /// Command given by the action of the player, none while the action is cooling down.
+    pub fn act(
+        &mut self,
+        player: usize,
+        action: PlayerAction,
+        now: f32,
+        targets: &[(i32, i32)],
+    ) -> Option<PlayerCommand> {
+        let player = self.list.get_mut(player)?;
+        let slot = PlayerAction::ALL.iter().position(|a| *a == action)?;
+        if now < player.ready_at[slot] {
+            return None;
+        }
+        let faction = player.faction;
+        let command = match action {
+            PlayerAction::Rally => {
+                if targets.is_empty() {
+                    return None;
+                }
+                let (x, y) = targets[player.rally % targets.len()];
+                player.rally = (player.rally + 1) % targets.len();
+                PlayerCommand::Rally { faction, x, y }
+            }
+            PlayerAction::Spawn => PlayerCommand::Spawn { faction },
+            PlayerAction::Charge => PlayerCommand::Charge { faction },
+        };
+        player.ready_at[slot] = now + action.cooldown();
+        Some(command)
+    }
+}
+
+/// Cells of the spawners a faction can rally on, the ones of its enemies first.
+fn rally_targets(
+    alliances: &Alliances,
+    faction: Faction,
+    spawners: impl Iterator<Item = (Faction, i32, i32)>,
+) -> Vec<(i32, i32)> {
+    let mut targets: Vec<_> = spawners
+        .map(|(owner, x, y)| (alliances.are_allies(faction, owner), x, y))
+        .collect();
+    targets.sort();
+    targets.into_iter().map(|(_, x, y)| (x, y)).collect()
+}
+
+/// Key of a player for one of its actions.
+#[derive(Component)]
+struct PlayerKey {
+    player: usize,
+    action: PlayerAction,
+}
+
+#[derive(Component)]
+struct PlayerHud;
+
+#[allow(clippy::too_many_arguments)]
+fn join_players(
+    mut commands: Commands,
+    bindings: Res<Bindings>,
+    keys: Res<Input<KeyCode>>,
+    pads: Res<Input<GamepadButton>>,
+    mut pool: ResMut<GamepadPool>,
+    mut players: ResMut<Players>,
+    scenarios: Res<Assets<Scenario>>,
+    current: Res<CurrentScenario>,
+) {
+    let gamepad = pool.just_pressed_free(&pads, GamepadButtonType::Start);
+    if gamepad.is_none() && !bindings.just_pressed(&keys, Action::Join) {
+        return;
+    }
+    // Only the factions of the battle can be played
+    let factions = match current.get(&scenarios) {
+        Some(scenario) => scenario.factions(),
+        None => return,
+    };
+    let player = match players.join(gamepad, &factions) {
+        Some(player) => player,
+        None => return,
+    };
+    info!(
+        "Player {} joins with faction {}",
+        player + 1,
+        players.list[player].faction.0
+    );
+    if let Some(gamepad) = gamepad {
+        pool.reserve(gamepad);
+    }
+    for action in PlayerAction::ALL {
+        let comb = match gamepad {
+            Some(gamepad) => CombinationInput::with_combination(Combination::Gamepad(
+                GamepadButton::new(gamepad, action.gamepad_button()),
+            )),
+            None => CombinationInput::new(true),
+        };
+        commands
+            .spawn()
+            .insert(comb)
+            .insert(PlayerKey { player, action });
+    }
+}
+
+/// Select makes the player of the gamepad leave, so does disconnecting the gamepad. Its keys
+/// go away and the gamepad can join again.
+fn leave_players(
+    mut commands: Commands,
+    pads: Res<Input<GamepadButton>>,
+    mut gamepad_events: EventReader<GamepadEvent>,
+    mut pool: ResMut<GamepadPool>,
+    mut players: ResMut<Players>,
+    mut keys: Query<(Entity, &mut PlayerKey)>,
+) {
+    let mut leaving: Vec<Gamepad> = pads
+        .get_just_pressed()
+        .filter(|pressed| pressed.button_type == GamepadButtonType::Select)
+        .map(|pressed| pressed.gamepad)
+        .collect();
+    leaving.extend(
+        gamepad_events
+            .iter()
+            .filter(|event| event.event_type == GamepadEventType::Disconnected)
+            .map(|event| event.gamepad),
+    );
+    // Keys despawned are still in the query until the end of the stage
+    let mut gone = Vec::new();
+    for gamepad in leaving {
+        pool.liberate(gamepad);
+        let player = match players.with_gamepad(gamepad) {
+            Some(player) => player,
+            None => continue,
+        };
+        players.leave(player);
+        info!("Player {} leaves", player + 1);
+        for (entity, mut key) in keys.iter_mut() {
+            if gone.contains(&entity) {
+                continue;
+            }
+            if key.player == player {
+                commands.entity(entity).despawn();
+                gone.push(entity);
+            } else if key.player > player {
+                key.player -= 1;
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn player_actions(
+    clock: Res<SimClock>,
+    keyboard: Res<Keyboard>,
+    pads: Res<Input<GamepadButton>>,
+    alliances: Res<Alliances>,
+    mut players: ResMut<Players>,
+    mut player_commands: ResMut<PlayerCommands>,
+    query: Query<(&PlayerKey, &CombinationInput)>,
+    spawners: Query<&SpawnInfo>,
+) {
+    for (key, comb) in query.iter() {
+        if !keyboard.just_pressed_t(comb) && !pads.just_pressed_t(comb) {
+            continue;
+        }
+        let faction = match players.get(key.player) {
+            Some(player) => player.faction,
+            None => continue,
+        };
+        let targets = rally_targets(
+            &alliances,
+            faction,
+            spawners.iter().map(|s| (s.faction, s.x, s.y)),
+        );
+        if let Some(command) = players.act(key.player, key.action, clock.elapsed(), &targets) {
+            player_commands.push(command);
+        }
+    }
+}
+
+fn spawn_player_hud(mut commands: Commands, spawner: Res<ButtonSpawner>) {
+    let mut text = spawner.text(String::new(), 25.0);
+    text.style = Style {
+        position_type: PositionType::Absolute,
+        position: UiRect {
+            top: Val::Px(80.0),
+            left: Val::Px(10.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands.spawn_bundle(text).insert(PlayerHud);
+}
+
+/// The keys of every player in its color, and how to join.
+fn update_player_hud(
+    bindings: Res<Bindings>,
+    players: Res<Players>,
+    keys: Query<(&PlayerKey, &CombinationInput)>,
+    changed: Query<(), Changed<CombinationInput>>,
+    mut query: Query<&mut Text, With<PlayerHud>>,
+) {
+    if !players.is_changed() && !bindings.is_changed() && changed.is_empty() {
+        return;
+    }
+    for mut text in query.iter_mut() {
+        let style = text.sections[0].style.clone();
+        let mut sections: Vec<TextSection> = players
+            .list
+            .iter()
+            .enumerate()
+            .map(|(i, player)| {
+                let mut actions: Vec<_> = keys.iter().filter(|(key, _)| key.player == i).collect();
+                actions.sort_by_key(|(key, _)| key.action as usize);
+                let actions: Vec<_> = actions
+                    .iter()
+                    .map(|(key, comb)| format!("{:?} {}", key.action, comb.to_string()))
+                    .collect();
+                TextSection {
+                    value: format!("Player {}: {}\n", i + 1, actions.join(", ")),
+                    style: TextStyle {
+                        color: player.faction.color(),
+                        ..style.clone()
+                    },
+                }
+            })
+            .collect();
+        sections.push(TextSection {
+            value: format!("{:?} or Start: join", bindings.key(Action::Join)),
+            style: TextStyle {
+                color: Color::rgb(0.9, 0.9, 0.9),
+                ..style
+            },
+        });
+        text.sections = sections;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn players_take_the_free_factions() {
+        let all: Vec<_> = Faction::all().collect();
+        let mut players = Players::default();
+        for i in 0..Faction::MAX {
+            assert_eq!(players.join(None, &all), Some(i));
+        }
+        assert_eq!(players.join(None, &all), None);
+        assert_eq!(players.get(3).map(|p| p.faction), Some(Faction(3)));
+
+        let mut players = Players::default();
+        assert_eq!(players.join(None, &[Faction(1), Faction(4)]), Some(0));
+        assert_eq!(players.join(None, &[Faction(1), Faction(4)]), Some(1));
+        assert_eq!(players.join(None, &[Faction(1), Faction(4)]), None);
+        assert_eq!(players.get(1).map(|p| p.faction), Some(Faction(4)));
+
+        let mut players = Players::default();
+        assert_eq!(players.mouse_faction(), Faction(0));
+        players.join(Some(Gamepad::new(0)), &all);
+        players.join(None, &all);
+        assert_eq!(players.mouse_player(), Some(1));
+        assert_eq!(players.mouse_faction(), Faction(1));
+    }
+
+    #[test]
+    fn leaving_player_frees_its_faction() {
+        let all: Vec<_> = Faction::all().collect();
+        let mut players = Players::default();
+        players.join(Some(Gamepad::new(0)), &all);
+        players.join(Some(Gamepad::new(1)), &all);
+        assert_eq!(players.with_gamepad(Gamepad::new(1)), Some(1));
+
+        assert_eq!(players.leave(0).map(|p| p.faction), Some(Faction(0)));
+        assert!(players.leave(1).is_none());
+        assert_eq!(players.with_gamepad(Gamepad::new(0)), None);
+        assert_eq!(players.with_gamepad(Gamepad::new(1)), Some(0));
+        assert_eq!(players.join(None, &all), Some(1));
+        assert_eq!(players.get(1).map(|p| p.faction), Some(Faction(0)));
+    }
+
+    #[test]
+    fn rally_goes_through_the_enemy_spawners_first() {
+        let alliances = Alliances::from_teams(&[0, 0, 1]);
+        let spawners = vec![(Faction(1), 0, 0), (Faction(2), 5, 5), (Faction(2), 2, 3)];
+        let targets = rally_targets(&alliances, Faction(0), spawners.into_iter());
+        assert_eq!(targets, vec![(2, 3), (5, 5), (0, 0)]);
+
+        let mut players = Players::default();
+        players.join(None, &[Faction(0)]);
+        let rally =
+            |players: &mut Players, now| match players.act(0, PlayerAction::Rally, now, &targets) {
+                Some(PlayerCommand::Rally { x, y, .. }) => Some((x, y)),
+                _ => None,
+            };
+        assert_eq!(rally(&mut players, 0.0), Some((2, 3)));
+        assert_eq!(rally(&mut players, 0.0), Some((5, 5)));
+        assert_eq!(rally(&mut players, 0.0), Some((0, 0)));
+        assert_eq!(rally(&mut players, 0.0), Some((2, 3)));
+
+        assert!(players
+            .act(0, PlayerAction::Charge, 1.0, &targets)
+            .is_some());
+        assert!(players
+            .act(0, PlayerAction::Charge, 10.0, &targets)
+            .is_none());
+        assert!(players
+            .act(0, PlayerAction::Charge, 16.0, &targets)
+            .is_some());
+    }
+}
@@ -0,0 +1,199 @@
+//! Shots of the ranged units. They fly toward their target during the simulation and only hurt
+//! it when they land.
+use bevy::prelude::*;
+
+use crate::anim::*;
+use crate::fx::*;
+use crate::grid::*;
+use crate::sim::*;
+use crate::unit::*;
+
+pub struct ProjectilePlugin;
+
+impl Plugin for ProjectilePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ProjectileSprite>()
+            .add_system_to_stage(SimStage, move_projectiles)
+            .add_system(add_projectile_sprite);
+    }
+}
+
+/// Cells traveled every second.
+const PROJECTILE_SPEED: f32 = 6.0;
+
+#[derive(Component)]
+pub struct Projectile {
+    pub damage: DamageEvent,
+    /// Last known position of the target, where the projectile lands if it dies before.
+    pub aim: (f32, f32),
+}
+
+impl Projectile {
+    pub fn spawn(
+        commands: &mut Commands,
+        x: i32,
+        y: i32,
+        damage: DamageEvent,
+        transform: &Transform,
+    ) {
+        commands
+            .spawn()
+            .insert(Projectile {
+                damage,
+                aim: (x as f32, y as f32),
+            })
+            .insert(GridTransform {
+                x: x as f32,
+                y: y as f32,
+                update_scale: false,
+            })
+            .insert(*transform);
+    }
+}
+
+struct ProjectileSprite {
+    handle: Handle<TextureAtlas>,
+}
+
+impl FromWorld for ProjectileSprite {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.get_resource::<AssetServer>().unwrap();
+        let texture = asset_server.load("spritesheet/effects/FireBall_64x64.png");
+        let mut texture_atlases = world.get_resource_mut::<Assets<TextureAtlas>>().unwrap();
+        let atlas = TextureAtlas::from_grid(texture, Vec2::new(64.0, 64.0), 45, 1);
+        Self {
+            handle: texture_atlases.add(atlas),
+        }
+    }
+}
+
+fn add_projectile_sprite(
+    mut commands: Commands,
+    sprite: Res<ProjectileSprite>,
+    query: Query<(Entity, &Transform), Added<Projectile>>,
+) {
+    for (entity, transform) in query.iter() {
+        let mut transform = *transform;
+        transform.scale = Vec3::splat(1.0);
+        commands
+            .entity(entity)
+            .insert_bundle(SpriteSheetBundle {
+                texture_atlas: sprite.handle.clone(),
+                transform,
+                ..Default::default()
+            })
+            .insert(Animation::new(AnimationMode::Loop, (0..45).collect()))
+            .insert(AnimTimer::new(0.03));
+    }
+}
+
+fn move_projectiles(
+    mut commands: Commands,
+    clock: Res<SimClock>,
+    mut damage_events: ResMut<Events<DamageEvent>>,
+    mut fx_events: ResMut<Events<FxSpawnEvent>>,
+    mut projectiles: Query<(Entity, &mut Projectile, &mut GridTransform, &Transform)>,
+    targets: Query<&GridTransform, Without<Projectile>>,
+) {
+    for (entity, mut projectile, mut position, transform) in projectiles.iter_mut() {
+        let target = targets.get(projectile.damage.target).ok();
+        if let Some(target) = target {
+            projectile.aim = (target.x, target.y);
+        }
+
+        let (dx, dy) = (projectile.aim.0 - position.x, projectile.aim.1 - position.y);
+        let distance = (dx * dx + dy * dy).sqrt();
+        let travel = PROJECTILE_SPEED * clock.step;
+        if distance > travel {
+            position.x += dx / distance * travel;
+            position.y += dy / distance * travel;
+            continue;
+        }
+
+        if target.is_some() {
+            damage_events.send(projectile.damage.clone());
+        }
+        fx_events.send(FxSpawnEvent {
+            kind: FxKind::Impact,
+            transform: *transform,
+            duration: Some(0.6),
+        });
+        commands.entity(entity).despawn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archetype::*;
+    use crate::camera::init_cameras_2d;
+    use crate::faction::*;
+    use crate::utils::tests::*;
+
+    #[test]
+    #[serial]
+    fn ranged_unit_kills_from_afar() {
+        fn init(
+            mut commands: Commands,
+            asset_server: Res<AssetServer>,
+            mut grid: ResMut<Grid>,
+            mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
+        ) {
+            spawn_unit(
+                &mut commands,
+                &asset_server,
+                &mut grid,
+                &mut texture_atlases,
+                &archetypes,
+                &ArchetypeId::new("archer"),
+                0,
+                0,
+                Faction(0),
+                |c| {
+                    c.insert(AttackingAI)
+                        .insert(AttackingAIState::MoveToNearestEnemy);
+                },
+            );
+            spawn_unit(
+                &mut commands,
+                &asset_server,
+                &mut grid,
+                &mut texture_atlases,
+                &archetypes,
+                &ArchetypeId::default(),
+                3,
+                0,
+                Faction(1),
+                |_| {},
+            );
+        }
+
+        fn check(
+            mut flag: ResMut<TestCheck<bool>>,
+            mut seen_projectile: Local<bool>,
+            projectiles: Query<&Projectile>,
+            units: Query<&UnitInfo>,
+        ) {
+            *seen_projectile |= !projectiles.is_empty();
+            // The archer never moved and its target died
+            **flag = *seen_projectile
+                && units.iter().len() == 1
+                && units.iter().all(|info| info.last_x == 0);
+        }
+
+        App::new()
+            .add_plugin(Test::Time(4.0))
+            .add_plugin(SimPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(UnitPlugin)
+            .add_plugin(ProjectilePlugin)
+            .add_system(init_cameras_2d)
+            .insert_resource(Grid::new(4, 1))
+            .insert_resource(TestCheck::new(false).is_true())
+            .add_startup_system(init)
+            .add_system(check)
+            .run();
+    }
+}
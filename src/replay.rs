@@ -0,0 +1,438 @@
+//! Record a battle to play it again later.
+//!
+//! The scenario and the seed are enough to get the same battle again, so a replay only stores
+//! them along with the player commands and their tick. The spawns are stored too, to notice when
+//! the battle played doesn't match the recorded one anymore.
+//!
+//! While playing, Space pauses, Up and Down change the speed and Left and Right seek 10 seconds
+//! backward or forward, or the keys rebound to these [Action]s.
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bindings::*;
+use crate::command::PlayerCommand;
+use crate::faction::*;
+use crate::scenario::*;
+use crate::sim::*;
+use crate::spawn::SpawnEvent;
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayMode>()
+            .init_resource::<Bindings>()
+            .add_event::<BattleStarted>()
+            .add_startup_system(start_replay)
+            .add_system(restart_recording.after(ScenarioSystem::Apply))
+            .add_system(replay_controls.before(ScenarioSystem::Apply))
+            .add_system(
+                seek_replay
+                    .after(replay_controls)
+                    .before(ScenarioSystem::Apply),
+            )
+            .add_system_to_stage(CoreStage::Last, check_replay_spawns)
+            .add_system_to_stage(CoreStage::Last, save_replay_on_exit);
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub scenario: String,
+    pub seed: u64,
+    pub step: f32,
+    /// Number of ticks recorded.
+    pub length: u64,
+    pub commands: Vec<(u64, PlayerCommand)>,
+    pub spawns: Vec<SpawnRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpawnRecord {
+    pub tick: u64,
+    pub faction: Faction,
+    pub x: i32,
+    pub y: i32,
+}
+
+impl From<&SpawnEvent> for SpawnRecord {
+    fn from(event: &SpawnEvent) -> Self {
+        SpawnRecord {
+            tick: event.tick,
+            faction: event.faction,
+            x: event.x,
+            y: event.y,
+        }
+    }
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        ron::de::from_str(&source).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let source = ron::ser::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, source).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Seconds of simulation recorded.
+    pub fn duration(&self) -> f32 {
+        self.length as f32 * self.step
+    }
+}
+
+/// Whether the battle is recorded, played from a replay or neither.
+#[derive(Default)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Record {
+        path: PathBuf,
+        replay: Replay,
+    },
+    Play(ReplayPlayer),
+}
+
+impl ReplayMode {
+    /// Record the battle and save it to `path` when the app exits.
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        ReplayMode::Record {
+            path: path.into(),
+            replay: Replay::default(),
+        }
+    }
+
+    pub fn play(replay: Replay) -> Self {
+        ReplayMode::Play(ReplayPlayer {
+            replay,
+            next_command: 0,
+            next_spawn: 0,
+            diverged_at: None,
+            seek_to: None,
+            finished: false,
+        })
+    }
+
+    pub fn player(&self) -> Option<&ReplayPlayer> {
+        match self {
+            ReplayMode::Play(player) => Some(player),
+            _ => None,
+        }
+    }
+
+    /// Commands to execute at `tick`, the `live` ones are ignored when playing a replay.
+    pub(crate) fn commands_for_tick(
+        &mut self,
+        tick: u64,
+        live: Vec<PlayerCommand>,
+    ) -> Vec<PlayerCommand> {
+        match self {
+            ReplayMode::Off => live,
+            ReplayMode::Record { replay, .. } => {
+                replay
+                    .commands
+                    .extend(live.iter().map(|command| (tick, command.clone())));
+                live
+            }
+            ReplayMode::Play(player) => player.commands_at(tick),
+        }
+    }
+}
+
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    next_command: usize,
+    next_spawn: usize,
+    diverged_at: Option<u64>,
+    seek_to: Option<u64>,
+    finished: bool,
+}
+
+impl ReplayPlayer {
+    /// First tick where the battle played differs from the recorded one.
+    pub fn diverged_at(&self) -> Option<u64> {
+        self.diverged_at
+    }
+
+    /// Go to the tick at the next frame, the battle restarts when seeking backward.
+    pub fn seek(&mut self, tick: u64) {
+        self.seek_to = Some(tick.min(self.replay.length));
+    }
+
+    fn rewind(&mut self) {
+        self.next_command = 0;
+        self.next_spawn = 0;
+        self.diverged_at = None;
+        self.finished = false;
+    }
+
+    fn commands_at(&mut self, tick: u64) -> Vec<PlayerCommand> {
+        let mut commands = Vec::new();
+        while let Some((command_tick, command)) = self.replay.commands.get(self.next_command) {
+            if *command_tick > tick {
+                break;
+            }
+            if *command_tick == tick {
+                commands.push(command.clone());
+            }
+            self.next_command += 1;
+        }
+        commands
+    }
+
+    fn check_spawn(&mut self, spawn: SpawnRecord) {
+        if self.diverged_at.is_some() {
+            return;
+        }
+        if self.replay.spawns.get(self.next_spawn) == Some(&spawn) {
+            self.next_spawn += 1;
+        } else {
+            warn!(
+                "Replay diverged at tick {}: unexpected {:?}",
+                spawn.tick, spawn
+            );
+            self.diverged_at = Some(spawn.tick);
+        }
+    }
+
+    /// Check nothing recorded is missing once the end of the replay is reached.
+    fn check_end(&mut self) {
+        if self.diverged_at.is_some() {
+            return;
+        }
+        if let Some(missing) = self.replay.spawns.get(self.next_spawn) {
+            warn!(
+                "Replay diverged at tick {}: missing {:?}",
+                missing.tick, missing
+            );
+            self.diverged_at = Some(missing.tick);
+        }
+    }
+}
+
+fn start_replay(mode: Res<ReplayMode>, mut clock: ResMut<SimClock>) {
+    if let Some(player) = mode.player() {
+        clock.step = player.replay.step;
+    }
+}
+
+fn replay_controls(
+    bindings: Res<Bindings>,
+    keys: Res<Input<KeyCode>>,
+    mut mode: ResMut<ReplayMode>,
+    mut clock: ResMut<SimClock>,
+) {
+    let player = match &mut *mode {
+        ReplayMode::Play(player) => player,
+        _ => return,
+    };
+    if bindings.just_pressed(&keys, Action::ReplayPause) {
+        clock.speed = if clock.speed == 0.0 { 1.0 } else { 0.0 };
+    }
+    if bindings.just_pressed(&keys, Action::ReplayFaster) {
+        clock.speed = (clock.speed * 2.0).clamp(0.25, 16.0);
+    }
+    if bindings.just_pressed(&keys, Action::ReplaySlower) {
+        clock.speed = (clock.speed / 2.0).clamp(0.25, 16.0);
+    }
+    let seek_ticks = (10.0 / clock.step) as u64;
+    if bindings.just_pressed(&keys, Action::ReplayBackward) {
+        player.seek(clock.tick().saturating_sub(seek_ticks));
+    }
+    if bindings.just_pressed(&keys, Action::ReplayForward) {
+        player.seek(clock.tick() + seek_ticks);
+    }
+}
+
+/// A new battle started, forget the previous one.
+fn restart_recording(mut events: EventReader<BattleStarted>, mut mode: ResMut<ReplayMode>) {
+    if events.iter().count() == 0 {
+        return;
+    }
+    if let ReplayMode::Record { replay, .. } = &mut *mode {
+        replay.commands.clear();
+        replay.spawns.clear();
+    }
+}
+
+/// Seeking backward restarts the battle, then the clock goes as fast as it can to the tick.
+fn seek_replay(
+    mut mode: ResMut<ReplayMode>,
+    mut clock: ResMut<SimClock>,
+    mut restart: EventWriter<RestartBattle>,
+) {
+    let player = match &mut *mode {
+        ReplayMode::Play(player) => player,
+        _ => return,
+    };
+    if let Some(tick) = player.seek_to.take() {
+        if tick < clock.tick() {
+            restart.send(RestartBattle);
+            player.rewind();
+        }
+        clock.fast_forward_to = Some(tick);
+    }
+}
+
+fn check_replay_spawns(
+    mut events: EventReader<SpawnEvent>,
+    mut mode: ResMut<ReplayMode>,
+    mut clock: ResMut<SimClock>,
+) {
+    match &mut *mode {
+        ReplayMode::Off => {}
+        ReplayMode::Record { replay, .. } => {
+            replay.spawns.extend(events.iter().map(SpawnRecord::from));
+        }
+        ReplayMode::Play(player) => {
+            for event in events.iter() {
+                player.check_spawn(event.into());
+            }
+            if !player.finished && clock.tick() >= player.replay.length {
+                info!("Replay finished");
+                player.finished = true;
+                player.check_end();
+                clock.speed = 0.0;
+            }
+        }
+    }
+}
+
+fn save_replay_on_exit(
+    mut exit: EventReader<AppExit>,
+    mut mode: ResMut<ReplayMode>,
+    clock: Res<SimClock>,
+    rng: Res<SimRng>,
+    scenario: Res<CurrentScenario>,
+) {
+    if exit.iter().count() == 0 {
+        return;
+    }
+    if let ReplayMode::Record { path, replay } = &mut *mode {
+        replay.scenario = scenario.path.clone();
+        replay.seed = rng.seed();
+        replay.step = clock.step;
+        replay.length = clock.tick();
+        match replay.save(path) {
+            Ok(()) => info!("Replay saved to {}", path.display()),
+            Err(e) => error!("Could not save the replay: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::*;
+    use crate::utils::tests::serial;
+
+    fn rally(x: i32) -> PlayerCommand {
+        PlayerCommand::Rally {
+            faction: Faction(0),
+            x,
+            y: 0,
+        }
+    }
+
+    fn spawn(tick: u64) -> SpawnRecord {
+        SpawnRecord {
+            tick,
+            faction: Faction(1),
+            x: 2,
+            y: 3,
+        }
+    }
+
+    fn replay() -> Replay {
+        Replay {
+            scenario: "scenarios/default.scenario.ron".to_string(),
+            seed: 42,
+            step: 0.1,
+            length: 100,
+            commands: vec![(2, rally(0)), (2, rally(1)), (5, rally(2))],
+            spawns: vec![spawn(1), spawn(30)],
+        }
+    }
+
+    #[test]
+    fn replay_round_trip() {
+        let path = std::env::temp_dir().join("multi_warrior_round_trip.replay.ron");
+        replay().save(&path).unwrap();
+        assert_eq!(Replay::load(&path).unwrap(), replay());
+        assert_eq!(replay().duration(), 10.0);
+    }
+
+    #[test]
+    fn record_keeps_live_commands() {
+        let mut mode = ReplayMode::record("unused");
+        assert_eq!(mode.commands_for_tick(1, vec![rally(0)]), vec![rally(0)]);
+        assert_eq!(mode.commands_for_tick(3, vec![rally(1)]), vec![rally(1)]);
+        match mode {
+            ReplayMode::Record { replay, .. } => {
+                assert_eq!(replay.commands, vec![(1, rally(0)), (3, rally(1))])
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn play_gives_recorded_commands() {
+        let mut mode = ReplayMode::play(replay());
+        assert_eq!(mode.commands_for_tick(1, vec![rally(7)]), vec![]);
+        assert_eq!(mode.commands_for_tick(2, vec![]), vec![rally(0), rally(1)]);
+        assert_eq!(mode.commands_for_tick(3, vec![]), vec![]);
+        assert_eq!(mode.commands_for_tick(5, vec![]), vec![rally(2)]);
+    }
+
+    #[test]
+    fn play_detects_divergence() {
+        let mut player = match ReplayMode::play(replay()) {
+            ReplayMode::Play(player) => player,
+            _ => unreachable!(),
+        };
+        player.check_spawn(spawn(1));
+        player.check_spawn(spawn(30));
+        player.check_end();
+        assert_eq!(player.diverged_at(), None);
+
+        player.rewind();
+        player.check_spawn(spawn(1));
+        player.check_end();
+        assert_eq!(player.diverged_at(), Some(30));
+
+        player.rewind();
+        player.check_spawn(spawn(2));
+        assert_eq!(player.diverged_at(), Some(2));
+    }
+
+    #[test]
+    #[serial]
+    fn recorded_battle_plays_back_the_same() {
+        let path = std::env::temp_dir().join("multi_warrior_play_back.replay.ron");
+        let mut app = App::new();
+        app.insert_resource(CurrentScenario::new("scenarios/skirmish.scenario.ron"))
+            .insert_resource(SimRng::new(5))
+            .insert_resource(ReplayMode::record(&path))
+            .add_plugin(Headless { seconds: 3.0 });
+        run_headless(&mut app);
+        let replay = Replay::load(&path).unwrap();
+        assert!(!replay.spawns.is_empty());
+
+        let mut app = App::new();
+        app.insert_resource(CurrentScenario::new(replay.scenario.clone()))
+            .insert_resource(SimRng::new(replay.seed))
+            .add_plugin(Headless {
+                seconds: replay.duration(),
+            })
+            .insert_resource(ReplayMode::play(replay));
+        run_headless(&mut app);
+        let player = app.world.resource::<ReplayMode>().player().unwrap();
+        assert_eq!(player.diverged_at(), None);
+    }
+}
@@ -0,0 +1,838 @@
+//! Battles described in RON files loaded through the asset server.
+//!
+//! ```ron
+//! (
+//!     width: 4,
+//!     height: 3,
+//!     // First row is the top of the map, see Terrain::from_char for the characters
+//!     terrain: [
+//!         "..T.",
+//!         ".##.",
+//!         "~~..",
+//!     ],
+//!     teams: [0, 1],
+//!     spawners: [
+//!         (x: 0, y: 0, faction: 0, target_unit_count: Some(3), spawn_delay: Some(1.0), ai: Attacking),
+//!         (x: 0, y: 2, faction: 0, archetype: "archer", stats: (life: 2), building: (life: 20)),
+//!         (x: 3, y: 0, faction: 1, waves: (
+//!             repeat: true,
+//!             escalation: 0.5,
+//!             list: [
+//!                 (delay: 5.0, groups: [(count: 3, interval: 1.0)]),
+//!                 (delay: 10.0, boss: true, groups: [(archetype: "boss", count: 1)]),
+//!             ],
+//!         )),
+//!     ],
+//!     units: [
+//!         (x: 3, y: 2, faction: 1, archetype: "knight", ai: MoveOnForce(target_x: 0, target_y: 0)),
+//!     ],
+//!     victory: [Annihilation, DestroySpawners],
+//! )
+//! ```
+//!
+//! Spawners and units are soldiers unless another [crate::archetype::Archetype] is given, and
+//! get the AI of their archetype when none is. A spawner with waves follows them instead of its
+//! count of units, see [crate::spawn::WaveScript]. Every spawner stands in a building holding
+//! its cell, which enemies can destroy or capture, see [crate::building]. The match ends with
+//! the first [crate::victory::VictoryCondition] met. A `territory` turns on the territory
+//! mode, see [crate::territory], and an `economy` lets the players buy the units of the
+//! spawners marked `bought: true`, see [crate::economy].
+use bevy::asset::{AssetLoader, LoadContext, LoadState, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::utils::BoxedFuture;
+use serde::Deserialize;
+
+use std::fmt;
+
+use crate::archetype::*;
+use crate::building::*;
+use crate::economy::*;
+use crate::faction::*;
+use crate::grid::*;
+use crate::projectile::Projectile;
+use crate::sim::*;
+use crate::spawn::*;
+use crate::template::*;
+use crate::territory::TerritoryDef;
+use crate::unit::*;
+use crate::victory::*;
+
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Scenario>()
+            .init_asset_loader::<ScenarioLoader>()
+            .init_resource::<Grid>()
+            .init_resource::<Alliances>()
+            .init_resource::<CurrentScenario>()
+            .add_event::<RestartBattle>()
+            .add_event::<BattleStarted>()
+            .add_startup_system(load_scenario)
+            .add_system(apply_scenario.label(ScenarioSystem::Apply));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum ScenarioSystem {
+    /// Place the scenario on the grid, systems restarting the battle must run before.
+    Apply,
+}
+
+/// Start the current scenario again from its first tick.
+pub struct RestartBattle;
+
+/// Sent once the scenario is placed on the grid.
+pub struct BattleStarted {
+    pub victory: Vec<VictoryCondition>,
+    pub territory: Option<TerritoryDef>,
+    pub economy: Option<EconomyDef>,
+}
+
+/// Scenario played by the game. Insert it before the plugin to choose another file.
+pub struct CurrentScenario {
+    pub path: String,
+    handle: Handle<Scenario>,
+}
+
+impl CurrentScenario {
+    pub fn new(path: impl Into<String>) -> Self {
+        CurrentScenario {
+            path: path.into(),
+            handle: Default::default(),
+        }
+    }
+
+    pub fn load_state(&self, asset_server: &AssetServer) -> LoadState {
+        asset_server.get_load_state(&self.handle)
+    }
+
+    /// The scenario, once loaded.
+    pub fn get<'a>(&self, scenarios: &'a Assets<Scenario>) -> Option<&'a Scenario> {
+        scenarios.get(&self.handle)
+    }
+}
+
+impl Default for CurrentScenario {
+    fn default() -> Self {
+        Self::new("scenarios/default.scenario.ron")
+    }
+}
+
+#[derive(Debug, Deserialize, TypeUuid)]
+#[uuid = "5a0d2f0e-3b2c-4c8e-9d0a-6f1c7e2b9a41"]
+pub struct Scenario {
+    pub width: i32,
+    pub height: i32,
+    #[serde(default)]
+    pub terrain: Vec<String>,
+    #[serde(default)]
+    pub teams: Vec<u8>,
+    #[serde(default)]
+    pub spawners: Vec<SpawnerDef>,
+    #[serde(default)]
+    pub units: Vec<UnitDef>,
+    /// Ways to win the match, annihilation when not given.
+    #[serde(default = "default_victory")]
+    pub victory: Vec<VictoryCondition>,
+    /// Paint and score the cells held, see [crate::territory].
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub territory: Option<TerritoryDef>,
+    /// Gold earned and spent on units, see [crate::economy].
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub economy: Option<EconomyDef>,
+}
+
+impl Scenario {
+    /// Factions with a spawner or a unit in the scenario, in order.
+    pub fn factions(&self) -> Vec<Faction> {
+        let mut factions: Vec<_> = self
+            .spawners
+            .iter()
+            .map(|spawner| spawner.faction)
+            .chain(self.units.iter().map(|unit| unit.faction))
+            .collect();
+        factions.sort();
+        factions.dedup();
+        factions
+    }
+}
+
+fn default_victory() -> Vec<VictoryCondition> {
+    vec![VictoryCondition::Annihilation]
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpawnerDef {
+    pub x: i32,
+    pub y: i32,
+    pub faction: Faction,
+    #[serde(default)]
+    pub target_unit_count: Option<u32>,
+    #[serde(default)]
+    pub spawn_delay: Option<f32>,
+    #[serde(default)]
+    pub archetype: ArchetypeId,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub ai: Option<AiKind>,
+    /// Changes to the stats of the archetype for the units spawned.
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub stats: Option<StatsOverride>,
+    /// Replaces the count of units and the delay by a script of waves.
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub waves: Option<WaveScript>,
+    /// Life of the spawner and time needed to capture it.
+    #[serde(default)]
+    pub building: BuildingDef,
+    /// Units are bought with the gold of the faction instead of coming on their own.
+    #[serde(default)]
+    pub bought: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnitDef {
+    pub x: i32,
+    pub y: i32,
+    pub faction: Faction,
+    #[serde(default)]
+    pub archetype: ArchetypeId,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub ai: Option<AiKind>,
+}
+
+/// Every problem found in a scenario file, with the line it comes from when known.
+#[derive(Debug)]
+pub struct ScenarioError {
+    pub file: String,
+    pub errors: Vec<(Option<usize>, String)>,
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (line, message)) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match line {
+                Some(line) => write!(f, "{}:{}: {}", self.file, line, message)?,
+                None => write!(f, "{}: {}", self.file, message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl Scenario {
+    pub fn from_bytes(file: &str, bytes: &[u8]) -> Result<Scenario, ScenarioError> {
+        let error = |errors| ScenarioError {
+            file: file.to_string(),
+            errors,
+        };
+        let source = std::str::from_utf8(bytes)
+            .map_err(|e| error(vec![(None, format!("Invalid UTF-8: {}", e))]))?;
+        let scenario = ron::de::from_str::<Scenario>(source).map_err(|e| {
+            error(vec![(
+                Some(e.position.line),
+                format!("{} (column {})", e.code, e.position.col),
+            )])
+        })?;
+
+        let errors = scenario.validate(source);
+        if errors.is_empty() {
+            Ok(scenario)
+        } else {
+            Err(error(errors))
+        }
+    }
+
+    fn validate(&self, source: &str) -> Vec<(Option<usize>, String)> {
+        let mut errors = Vec::new();
+
+        if self.width <= 0 || self.height <= 0 {
+            errors.push((
+                None,
+                format!("Invalid grid size {}x{}", self.width, self.height),
+            ));
+            return errors;
+        }
+
+        let terrain_lines = list_element_lines(source, "terrain");
+        if !self.terrain.is_empty() && self.terrain.len() != self.height as usize {
+            errors.push((
+                terrain_lines.first().cloned(),
+                format!(
+                    "Expected {} terrain rows, found {}",
+                    self.height,
+                    self.terrain.len()
+                ),
+            ));
+        }
+        for (i, row) in self.terrain.iter().enumerate() {
+            let line = terrain_lines.get(i).cloned();
+            if row.chars().count() != self.width as usize {
+                errors.push((
+                    line,
+                    format!(
+                        "Expected {} cells in terrain row, found {}",
+                        self.width,
+                        row.chars().count()
+                    ),
+                ));
+            }
+            for c in row.chars().filter(|c| Terrain::from_char(*c).is_none()) {
+                errors.push((line, format!("Unknown terrain '{}'", c)));
+            }
+        }
+
+        if self.teams.len() > Faction::MAX {
+            errors.push((None, format!("At most {} teams can be given", Faction::MAX)));
+        }
+
+        let grid = self.build_grid();
+        let check_cell = |errors: &mut Vec<(Option<usize>, String)>,
+                          line: Option<usize>,
+                          what: &str,
+                          x: i32,
+                          y: i32,
+                          faction: Faction| {
+            if faction.index() >= Faction::MAX {
+                errors.push((
+                    line,
+                    format!(
+                        "{} has faction {}, the maximum is {}",
+                        what,
+                        faction.0,
+                        Faction::MAX - 1
+                    ),
+                ));
+            }
+            if grid.get_terrain(x, y).is_none() {
+                errors.push((
+                    line,
+                    format!(
+                        "{} at ({}, {}) is outside the {}x{} grid",
+                        what, x, y, self.width, self.height
+                    ),
+                ));
+            } else if !grid.is_walkable(x, y) {
+                errors.push((line, format!("{} at ({}, {}) is in a wall", what, x, y)));
+            }
+        };
+
+        // Spawners are buildings holding their cell, like units
+        let mut occupied = Grid::new(self.width, self.height);
+        let spawner_lines = list_element_lines(source, "spawners");
+        for (i, spawner) in self.spawners.iter().enumerate() {
+            let line = spawner_lines.get(i).cloned();
+            check_cell(
+                &mut errors,
+                line,
+                "Spawner",
+                spawner.x,
+                spawner.y,
+                spawner.faction,
+            );
+            if occupied.get_count(spawner.x, spawner.y).unwrap_or(0) > 0 {
+                errors.push((
+                    line,
+                    format!(
+                        "Spawner at ({}, {}) is on another spawner",
+                        spawner.x, spawner.y
+                    ),
+                ));
+            } else {
+                occupied.add_unit(spawner.x, spawner.y, spawner.faction);
+            }
+            if spawner.building.life <= 0 {
+                errors.push((line, "Spawner building needs some life".to_string()));
+            }
+            if spawner.bought && self.economy.is_none() {
+                errors.push((line, "Spawner sells units without an economy".to_string()));
+            }
+            if spawner.bought && spawner.waves.is_some() {
+                errors.push((
+                    line,
+                    "Spawner can't both sell units and send waves".to_string(),
+                ));
+            }
+        }
+
+        let victory_lines = list_element_lines(source, "victory");
+        for (i, condition) in self.victory.iter().enumerate() {
+            let line = victory_lines.get(i).cloned();
+            match condition {
+                VictoryCondition::Territory(part) if *part <= 0.0 || *part > 1.0 => {
+                    errors.push((
+                        line,
+                        format!("Territory needs a part of the map in ]0, 1], not {}", part),
+                    ));
+                }
+                VictoryCondition::Survive { faction, .. } if faction.index() >= Faction::MAX => {
+                    errors.push((
+                        line,
+                        format!(
+                            "Survive has faction {}, the maximum is {}",
+                            faction.0,
+                            Faction::MAX - 1
+                        ),
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(territory) = &self.territory {
+            if territory.interval <= 0.0 {
+                errors.push((
+                    list_element_lines(source, "territory.interval")
+                        .first()
+                        .cloned(),
+                    format!(
+                        "Territory needs a positive interval, not {}",
+                        territory.interval
+                    ),
+                ));
+            }
+            let hill_lines = list_element_lines(source, "territory.hill");
+            for (i, (x, y)) in territory.hill.iter().enumerate() {
+                if !grid.is_walkable(*x, *y) {
+                    errors.push((
+                        hill_lines.get(i).cloned(),
+                        format!("Hill cell ({}, {}) can't be walked on", x, y),
+                    ));
+                }
+            }
+        }
+
+        let unit_lines = list_element_lines(source, "units");
+        for (i, unit) in self.units.iter().enumerate() {
+            let line = unit_lines.get(i).cloned();
+            check_cell(&mut errors, line, "Unit", unit.x, unit.y, unit.faction);
+            if occupied.get_count(unit.x, unit.y).is_some()
+                && !occupied.add_unit(unit.x, unit.y, unit.faction)
+            {
+                errors.push((
+                    line,
+                    format!(
+                        "Unit at ({}, {}) is on a cell held by another faction",
+                        unit.x, unit.y
+                    ),
+                ));
+            }
+        }
+
+        errors
+    }
+
+    /// Grid of the scenario with its terrain, without any unit on it.
+    pub fn build_grid(&self) -> Grid {
+        let mut grid = Grid::new(self.width, self.height);
+        for (row, line) in self.terrain.iter().enumerate() {
+            let y = self.height - 1 - row as i32;
+            for (x, c) in line.chars().enumerate() {
+                if let Some(terrain) = Terrain::from_char(c) {
+                    grid.set_terrain(x as i32, y, terrain);
+                }
+            }
+        }
+        grid
+    }
+}
+
+/// Line of each element of the list at `path`, like `spawners` or `territory.hill`, so
+/// validation errors can point at the element they are about. A value that is not a list gives
+/// its own line. This only understands enough RON to skip strings and comments.
+fn list_element_lines(source: &str, path: &str) -> Vec<usize> {
+    let path: Vec<&str> = path.split('.').collect();
+    let mut lines = Vec::new();
+    let mut line = 1;
+    let mut depth = 0;
+    // Structs of the path the cursor is in
+    let mut entered = 0;
+    let mut word = String::new();
+    let mut word_done = false;
+    let mut field_found = false;
+    let mut list_depth = None;
+    let mut expect_element = false;
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            line += 1;
+        }
+        if c.is_whitespace() {
+            word_done = true;
+            continue;
+        }
+        if c == '/' && chars.peek() == Some(&'/') {
+            for c in chars.by_ref() {
+                if c == '\n' {
+                    line += 1;
+                    break;
+                }
+            }
+            continue;
+        }
+        if c == '/' && chars.peek() == Some(&'*') {
+            let mut previous = ' ';
+            for c in chars.by_ref() {
+                if c == '\n' {
+                    line += 1;
+                }
+                if previous == '*' && c == '/' {
+                    break;
+                }
+                previous = c;
+            }
+            continue;
+        }
+
+        if expect_element && list_depth == Some(depth) && c != ',' && c != ']' {
+            lines.push(line);
+            expect_element = false;
+        }
+
+        let after_colon = field_found;
+        field_found = false;
+        let last = entered + 1 == path.len();
+        if after_colon && last && c != '[' {
+            lines.push(line);
+            return lines;
+        }
+        match c {
+            '"' => {
+                let mut escaped = false;
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    if c == '"' && !escaped {
+                        break;
+                    }
+                    escaped = c == '\\' && !escaped;
+                }
+            }
+            '[' if after_colon && last && list_depth.is_none() => {
+                depth += 1;
+                list_depth = Some(depth);
+                expect_element = true;
+            }
+            '(' | '{' if after_colon => {
+                depth += 1;
+                entered += 1;
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                if list_depth == Some(depth) {
+                    return lines;
+                }
+                if entered > 0 && depth == entered + 1 {
+                    entered -= 1;
+                }
+                depth -= 1;
+            }
+            ',' if list_depth == Some(depth) => expect_element = true,
+            ':' => field_found = depth == entered + 1 && word == path[entered],
+            c if c.is_alphanumeric() || c == '_' => {
+                if word_done {
+                    word.clear();
+                    word_done = false;
+                }
+                word.push(c);
+                continue;
+            }
+            _ => {}
+        }
+        word.clear();
+        word_done = false;
+    }
+
+    lines
+}
+
+#[derive(Default)]
+pub struct ScenarioLoader;
+
+impl AssetLoader for ScenarioLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let file = load_context.path().display().to_string();
+            let scenario = Scenario::from_bytes(&file, bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(scenario));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["scenario.ron"]
+    }
+}
+
+fn load_scenario(asset_server: Res<AssetServer>, mut current: ResMut<CurrentScenario>) {
+    current.handle = asset_server.load(current.path.as_str());
+}
+
+/// Despawn everything from the previous battle and place the scenario on a new grid. Runs again
+/// on [RestartBattle] or when the file changes so designers can iterate with hot reloading. The
+/// clock and random numbers restart too, so a battle only depends on the scenario and the seed.
+/// The scenario waits for the archetypes, its units could use the ones of the file.
+#[allow(clippy::too_many_arguments)]
+fn apply_scenario(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<Scenario>>,
+    mut restarts: EventReader<RestartBattle>,
+    mut pending: Local<bool>,
+    scenarios: Res<Assets<Scenario>>,
+    current: Res<CurrentScenario>,
+    asset_server: Res<AssetServer>,
+    archetypes: Res<Archetypes>,
+    mut grid: ResMut<Grid>,
+    mut alliances: ResMut<Alliances>,
+    mut clock: ResMut<SimClock>,
+    mut rng: ResMut<SimRng>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    mut started: EventWriter<BattleStarted>,
+    battle_entities: Query<Entity, Or<(With<UnitForce>, With<SpawnInfo>, With<Projectile>)>>,
+) {
+    let changed = events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            *handle == current.handle
+        }
+        AssetEvent::Removed { .. } => false,
+    });
+    let restart = restarts.iter().count() > 0;
+    *pending |= changed || restart;
+    if !*pending || !archetypes.is_ready(&asset_server) {
+        return;
+    }
+    *pending = false;
+    let scenario = match scenarios.get(&current.handle) {
+        Some(scenario) => scenario,
+        None => return,
+    };
+    info!("Starting scenario {}", current.path);
+    let unknown = scenario
+        .spawners
+        .iter()
+        .map(|spawner| &spawner.archetype)
+        .chain(scenario.units.iter().map(|unit| &unit.archetype))
+        .chain(
+            scenario
+                .spawners
+                .iter()
+                .filter_map(|spawner| spawner.waves.as_ref())
+                .flat_map(|waves| waves.list.iter())
+                .flat_map(|wave| wave.groups.iter())
+                .map(|group| &group.archetype),
+        )
+        .chain(
+            scenario
+                .economy
+                .iter()
+                .flat_map(|economy| economy.prices.keys()),
+        )
+        .filter(|id| archetypes.get(id).is_none());
+    for id in unknown {
+        warn!("{} uses the unknown archetype {}", current.path, id);
+    }
+
+    for entity in battle_entities.iter() {
+        commands.entity(entity).despawn();
+    }
+    *grid = scenario.build_grid();
+    *alliances = Alliances::from_teams(&scenario.teams);
+    clock.reset();
+    rng.reset();
+
+    for spawner in scenario.spawners.iter() {
+        let mut template =
+            UnitTemplate::new(spawner.archetype.clone()).with_stats(spawner.stats.clone());
+        if let Some(ai) = &spawner.ai {
+            template = template.with_ai(ai);
+        }
+        let mut entity = spawn_building(
+            &mut commands,
+            &mut grid,
+            spawner.x,
+            spawner.y,
+            spawner.faction,
+            &spawner.building,
+        );
+        entity.insert(SpawnInfo {
+            target_unit_count: spawner.target_unit_count,
+            spawn_delay: spawner.spawn_delay,
+            last_spawn: f32::MIN,
+            faction: spawner.faction,
+            x: spawner.x,
+            y: spawner.y,
+            template,
+        });
+        if let Some(waves) = &spawner.waves {
+            entity.insert(SpawnWaves::new(waves.clone()));
+        }
+        if spawner.bought {
+            entity.insert(SpawnQueue::new(spawner.faction));
+        }
+    }
+
+    for unit in scenario.units.iter() {
+        spawn_unit(
+            &mut commands,
+            &asset_server,
+            &mut grid,
+            &mut texture_atlases,
+            &archetypes,
+            &unit.archetype,
+            unit.x,
+            unit.y,
+            unit.faction,
+            |c| match &unit.ai {
+                Some(ai) => ai.insert(c),
+                None => archetypes.get_or_default(&unit.archetype).ai.insert(c),
+            },
+        );
+    }
+
+    started.send(BattleStarted {
+        victory: scenario.victory.clone(),
+        territory: scenario.territory.clone(),
+        economy: scenario.economy.clone(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"(
+    width: 4,
+    height: 3,
+    terrain: [
+        "..T.",
+        ".##.",
+        "~~..",
+    ],
+    teams: [0, 1],
+    spawners: [
+        (x: 0, y: 0, faction: 0, target_unit_count: Some(3)),
+    ],
+    units: [
+        (x: 3, y: 2, faction: 1, ai: MoveOnForce(target_x: 0, target_y: 0)),
+        (x: 3, y: 1, faction: 1, archetype: "archer", ai: Turning),
+    ],
+)"#;
+
+    #[test]
+    fn parse_valid_scenario() {
+        let scenario = Scenario::from_bytes("valid", VALID.as_bytes()).unwrap();
+        assert_eq!(scenario.spawners.len(), 1);
+        assert_eq!(scenario.units.len(), 2);
+        assert_eq!(scenario.units[0].faction, Faction(1));
+        assert_eq!(scenario.units[1].archetype, ArchetypeId::new("archer"));
+        assert!(scenario.spawners[0].ai.is_none());
+        assert_eq!(scenario.victory, vec![VictoryCondition::Annihilation]);
+        assert_eq!(scenario.factions(), vec![Faction(0), Faction(1)]);
+
+        let grid = scenario.build_grid();
+        assert_eq!(grid.get_terrain(2, 2), Some(Terrain::Forest));
+        assert_eq!(grid.get_terrain(1, 1), Some(Terrain::Wall));
+        assert_eq!(grid.get_terrain(0, 0), Some(Terrain::Water));
+        assert_eq!(grid.get_terrain(3, 0), Some(Terrain::Plain));
+    }
+
+    #[test]
+    fn syntax_error_has_line() {
+        let source = VALID.replace("(x: 3, y: 1", "(x: 3 y: 1");
+        let error = Scenario::from_bytes("broken", source.as_bytes()).unwrap_err();
+        assert_eq!(error.errors.len(), 1);
+        assert_eq!(error.errors[0].0, Some(15));
+    }
+
+    #[test]
+    fn validation_error_has_line() {
+        let source = VALID
+            .replace("(x: 3, y: 1", "(x: 7, y: 1")
+            .replace("\"..T.\"", "\"..X.\"");
+        let error = Scenario::from_bytes("invalid", source.as_bytes()).unwrap_err();
+        let lines: Vec<_> = error.errors.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, vec![Some(5), Some(15)]);
+        assert!(error
+            .to_string()
+            .starts_with("invalid:5: Unknown terrain 'X'"));
+    }
+
+    #[test]
+    fn shipped_scenarios_are_valid() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/scenarios");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let bytes = std::fs::read(&path).unwrap();
+            if let Err(e) = Scenario::from_bytes(&path.display().to_string(), &bytes) {
+                panic!("{}", e);
+            }
+        }
+    }
+
+    #[test]
+    fn victory_conditions_are_checked() {
+        let source = VALID.replace("    ],\n)", "    ],\n    victory: [Territory(1.5)],\n)");
+        let error = Scenario::from_bytes("victory", source.as_bytes()).unwrap_err();
+        assert_eq!(error.errors.len(), 1);
+        assert_eq!(error.errors[0].0, Some(17));
+    }
+
+    #[test]
+    fn hill_is_on_walkable_cells() {
+        let source = VALID.replace(
+            "    ],\n)",
+            "    ],\n    territory: (hill: [(0, 0), (1, 1)]),\n)",
+        );
+        let error = Scenario::from_bytes("hill", source.as_bytes()).unwrap_err();
+        assert_eq!(error.errors.len(), 1);
+        assert!(error.errors[0].1.contains("(1, 1)"));
+        assert_eq!(error.errors[0].0, Some(17));
+
+        let source = VALID.replace(
+            "    ],\n)",
+            "    ],\n    territory: (\n        interval: 0.0,\n        hill: [\n            (0, 0),\n            (1, 1),\n        ],\n    ),\n)",
+        );
+        let error = Scenario::from_bytes("hill", source.as_bytes()).unwrap_err();
+        let lines: Vec<_> = error.errors.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [Some(18), Some(21)]);
+    }
+
+    #[test]
+    fn bought_spawners_need_an_economy() {
+        let source = VALID.replace("target_unit_count: Some(3)", "bought: true");
+        let error = Scenario::from_bytes("economy", source.as_bytes()).unwrap_err();
+        assert_eq!(error.errors.len(), 1);
+        assert_eq!(error.errors[0].0, Some(11));
+
+        let source = source.replace("    ],\n)", "    ],\n    economy: (start: 100),\n)");
+        let scenario = Scenario::from_bytes("economy", source.as_bytes()).unwrap();
+        assert!(scenario.spawners[0].bought);
+    }
+
+    #[test]
+    fn spawners_hold_their_cell() {
+        let source = VALID.replace("(x: 3, y: 2", "(x: 0, y: 0");
+        let error = Scenario::from_bytes("spawner", source.as_bytes()).unwrap_err();
+        assert_eq!(error.errors.len(), 1);
+        assert_eq!(error.errors[0].0, Some(14));
+    }
+
+    #[test]
+    fn unit_in_wall_is_invalid() {
+        let source = VALID.replace("(x: 3, y: 1", "(x: 1, y: 1");
+        let error = Scenario::from_bytes("wall", source.as_bytes()).unwrap_err();
+        assert_eq!(error.errors.len(), 1);
+        assert_eq!(error.errors[0].0, Some(15));
+    }
+}
@@ -0,0 +1,172 @@
+//! Units selected with the mouse and ordered around.
+//!
+//! A left click selects the units of a cell, dragging selects the ones in the box. A right click
+//! orders the selected units to attack the enemy on the cell, or to walk there. The orders are
+//! sent as [PlayerCommand::Order] so they are recorded in replays. Only the units of the faction
+//! of the mouse, see [Players::mouse_faction], can be selected.
+use bevy::prelude::*;
+
+use crate::building::Building;
+use crate::command::*;
+use crate::picking::CursorCell;
+use crate::player::Players;
+use crate::state::AppState;
+use crate::unit::*;
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(spawn_selection_box)
+            .add_system(highlight_selection)
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(select_units)
+                    .with_system(order_selected_units),
+            );
+    }
+}
+
+#[derive(Component)]
+pub struct Selected;
+
+/// Box drawn while dragging, in screen coordinates.
+#[derive(Component)]
+struct SelectionBox;
+
+// Smaller drags are clicks
+const DRAG_THRESHOLD: f32 = 5.0;
+
+const SELECTED_COLOR: Color = Color::rgb(1.0, 1.0, 0.5);
+
+fn spawn_selection_box(mut commands: Commands) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                ..Default::default()
+            },
+            color: Color::rgba(1.0, 1.0, 0.5, 0.2).into(),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(SelectionBox);
+}
+
+/// Select on release, the box follows the cursor until then.
+#[allow(clippy::too_many_arguments)]
+fn select_units(
+    mut commands: Commands,
+    mut drag_start: Local<Option<(Vec2, (i32, i32))>>,
+    mouse: Res<Input<MouseButton>>,
+    cursor: Res<CursorCell>,
+    players: Res<Players>,
+    buttons: Query<&Interaction>,
+    units: Query<(Entity, &UnitInfo, &UnitForce, Option<&Selected>), Without<Building>>,
+    mut boxes: Query<(&mut Style, &mut Visibility), With<SelectionBox>>,
+) {
+    let (screen, cell) = (cursor.screen, cursor.cell);
+    if mouse.just_pressed(MouseButton::Left) {
+        // Clicks on the buttons are not for the battle
+        let on_button = buttons.iter().any(|i| *i != Interaction::None);
+        *drag_start = match (screen, cell) {
+            (Some(screen), Some(cell)) if !on_button => Some((screen, cell)),
+            _ => None,
+        };
+    }
+    let (start, start_cell) = match *drag_start {
+        Some(start) => start,
+        None => return,
+    };
+    let screen = screen.unwrap_or(start);
+    let dragging = start.distance(screen) > DRAG_THRESHOLD;
+    for (mut style, mut visibility) in boxes.iter_mut() {
+        let (min, max) = (start.min(screen), start.max(screen));
+        style.position = UiRect {
+            left: Val::Px(min.x),
+            bottom: Val::Px(min.y),
+            ..Default::default()
+        };
+        style.size = Size::new(Val::Px(max.x - min.x), Val::Px(max.y - min.y));
+        let visible = dragging && mouse.pressed(MouseButton::Left);
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+    }
+    if !mouse.just_released(MouseButton::Left) {
+        return;
+    }
+    *drag_start = None;
+
+    let end_cell = if dragging {
+        cell.unwrap_or(start_cell)
+    } else {
+        start_cell
+    };
+    let (min_x, max_x) = (start_cell.0.min(end_cell.0), start_cell.0.max(end_cell.0));
+    let (min_y, max_y) = (start_cell.1.min(end_cell.1), start_cell.1.max(end_cell.1));
+    let faction = players.mouse_faction();
+    for (entity, info, force, selected) in units.iter() {
+        let inside = (min_x..=max_x).contains(&info.target_x)
+            && (min_y..=max_y).contains(&info.target_y)
+            && force.faction == faction;
+        match (inside, selected.is_some()) {
+            (true, false) => {
+                commands.entity(entity).insert(Selected);
+            }
+            (false, true) => {
+                commands.entity(entity).remove::<Selected>();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn order_selected_units(
+    mouse: Res<Input<MouseButton>>,
+    cursor: Res<CursorCell>,
+    players: Res<Players>,
+    mut player_commands: ResMut<PlayerCommands>,
+    selected: Query<&UnitInfo, With<Selected>>,
+) {
+    if !mouse.just_pressed(MouseButton::Right) {
+        return;
+    }
+    let (x, y) = match cursor.cell {
+        Some(cell) => cell,
+        None => return,
+    };
+    let mut units: Vec<(i32, i32)> = selected
+        .iter()
+        .map(|info| (info.target_x, info.target_y))
+        .collect();
+    if units.is_empty() {
+        return;
+    }
+    units.sort_unstable();
+    units.dedup();
+    player_commands.push(PlayerCommand::Order {
+        faction: players.mouse_faction(),
+        units,
+        x,
+        y,
+    });
+}
+
+fn highlight_selection(
+    mut units: Query<
+        (&mut TextureAtlasSprite, Option<&Selected>),
+        (With<UnitForce>, Without<Building>),
+    >,
+) {
+    for (mut sprite, selected) in units.iter_mut() {
+        let color = if selected.is_some() {
+            SELECTED_COLOR
+        } else {
+            Color::WHITE
+        };
+        if sprite.color != color {
+            sprite.color = color;
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! Fixed timestep and seeded randomness so the same seed always gives the same battle.
+//!
+//! Battle systems go in [SimStage], a single threaded stage running after [CoreStage::Update]
+//! zero or more times per frame. They must read the time from [SimClock] and draw their random
+//! numbers from [SimRng], never from [Time] or `rand::random`.
+use bevy::ecs::schedule::ShouldRun;
+use bevy::prelude::*;
+
+use rand::rngs::StdRng;
+use rand::{Error, RngCore, SeedableRng};
+
+pub struct SimPlugin;
+
+impl Plugin for SimPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimClock>()
+            .init_resource::<SimRng>()
+            .add_stage_after(
+                CoreStage::Update,
+                SimStage,
+                SystemStage::single_threaded().with_run_criteria(sim_step),
+            );
+    }
+}
+
+/// Systems of this stage run in the order they were added, once per simulation step.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, StageLabel)]
+pub struct SimStage;
+
+/// Time of the simulation, advanced by fixed steps.
+pub struct SimClock {
+    /// Seconds of simulation per step.
+    pub step: f32,
+    /// Seconds of simulation per real second.
+    pub speed: f32,
+    /// Ignore the real time and do exactly one step per frame, to simulate as fast as possible.
+    pub one_step_per_frame: bool,
+    /// Step without waiting for the real time until this tick is reached.
+    pub fast_forward_to: Option<u64>,
+    /// No step is done while the match is paused or over.
+    pub paused: bool,
+    tick: u64,
+    accumulator: f32,
+    looping: bool,
+    frame_steps: u32,
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        SimClock {
+            step: 1.0 / 60.0,
+            speed: 1.0,
+            one_step_per_frame: false,
+            fast_forward_to: None,
+            paused: false,
+            tick: 0,
+            accumulator: 0.0,
+            looping: false,
+            frame_steps: 0,
+        }
+    }
+}
+
+impl SimClock {
+    /// Avoid simulating minutes at once after a freeze.
+    const MAX_FRAME_DELTA: f32 = 0.25;
+    /// Keep the app responsive while fast forwarding.
+    const MAX_FAST_FORWARD_STEPS: u32 = 600;
+
+    /// Clock ignoring the real time, to simulate as fast as possible.
+    pub fn one_step_per_frame() -> Self {
+        SimClock {
+            one_step_per_frame: true,
+            ..Default::default()
+        }
+    }
+
+    /// Number of steps done since the start of the battle.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Seconds of simulation since the start of the battle.
+    pub fn elapsed(&self) -> f32 {
+        self.tick as f32 * self.step
+    }
+
+    pub fn reset(&mut self) {
+        self.tick = 0;
+        self.accumulator = 0.0;
+        self.looping = false;
+    }
+
+    /// Called until it returns No every frame, `delta` being the real time of the frame.
+    fn advance(&mut self, delta: f32) -> ShouldRun {
+        if self.paused {
+            self.looping = false;
+            return ShouldRun::No;
+        }
+        if !self.looping {
+            self.frame_steps = 0;
+        }
+        if let Some(target) = self.fast_forward_to {
+            if self.tick >= target {
+                self.fast_forward_to = None;
+                self.accumulator = 0.0;
+            } else if self.frame_steps >= Self::MAX_FAST_FORWARD_STEPS {
+                self.looping = false;
+                return ShouldRun::No;
+            } else {
+                self.looping = true;
+                self.frame_steps += 1;
+                self.tick += 1;
+                return ShouldRun::YesAndCheckAgain;
+            }
+        }
+
+        if self.one_step_per_frame {
+            self.looping = !self.looping;
+            if !self.looping {
+                return ShouldRun::No;
+            }
+        } else {
+            if !self.looping {
+                self.accumulator += delta.min(Self::MAX_FRAME_DELTA) * self.speed;
+            }
+            if self.accumulator < self.step {
+                self.looping = false;
+                return ShouldRun::No;
+            }
+            self.accumulator -= self.step;
+            self.looping = true;
+        }
+        self.frame_steps += 1;
+        self.tick += 1;
+        ShouldRun::YesAndCheckAgain
+    }
+}
+
+fn sim_step(time: Res<Time>, mut clock: ResMut<SimClock>) -> ShouldRun {
+    clock.advance(time.delta_seconds())
+}
+
+/// Random number generator of the simulation. Insert it before the plugins to choose the seed.
+pub struct SimRng {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        Self::new(rand::random())
+    }
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        SimRng {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Restart the sequence of numbers from the seed.
+    pub fn reset(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+}
+
+impl RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let mut a = SimRng::new(42);
+        let mut b = SimRng::new(42);
+        let first: Vec<u32> = (0..10).map(|_| a.gen()).collect();
+        assert_eq!(first, (0..10).map(|_| b.gen()).collect::<Vec<u32>>());
+
+        a.reset();
+        assert_eq!(first, (0..10).map(|_| a.gen()).collect::<Vec<u32>>());
+    }
+
+    fn steps_in_frame(clock: &mut SimClock, delta: f32) -> u32 {
+        let mut steps = 0;
+        while clock.advance(delta) == ShouldRun::YesAndCheckAgain {
+            steps += 1;
+        }
+        steps
+    }
+
+    #[test]
+    fn clock_accumulates_real_time() {
+        let mut clock = SimClock {
+            step: 0.1,
+            ..Default::default()
+        };
+        assert_eq!(steps_in_frame(&mut clock, 0.05), 0);
+        assert_eq!(steps_in_frame(&mut clock, 0.21), 2);
+        assert_eq!(clock.tick(), 2);
+
+        clock.speed = 2.0;
+        assert_eq!(steps_in_frame(&mut clock, 0.1), 2);
+        assert_eq!(clock.tick(), 4);
+    }
+
+    #[test]
+    fn clock_one_step_per_frame() {
+        let mut clock = SimClock::one_step_per_frame();
+        for _ in 0..3 {
+            assert_eq!(steps_in_frame(&mut clock, 10.0), 1);
+        }
+        assert_eq!(clock.tick(), 3);
+        clock.reset();
+        assert_eq!(clock.elapsed(), 0.0);
+    }
+
+    #[test]
+    fn paused_clock_keeps_its_time() {
+        let mut clock = SimClock {
+            step: 0.1,
+            paused: true,
+            ..Default::default()
+        };
+        assert_eq!(steps_in_frame(&mut clock, 1.0), 0);
+        clock.paused = false;
+        assert_eq!(steps_in_frame(&mut clock, 0.15), 1);
+        assert_eq!(clock.tick(), 1);
+    }
+
+    #[test]
+    fn clock_fast_forward() {
+        let mut clock = SimClock {
+            fast_forward_to: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(
+            steps_in_frame(&mut clock, 0.0),
+            SimClock::MAX_FAST_FORWARD_STEPS
+        );
+        assert_eq!(steps_in_frame(&mut clock, 0.0), 400);
+        assert_eq!(clock.tick(), 1000);
+        assert_eq!(steps_in_frame(&mut clock, 0.0), 0);
+        assert_eq!(clock.fast_forward_to, None);
+    }
+}
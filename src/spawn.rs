@@ -1,44 +1,87 @@
+//! Spawners bring new units in the battle, either one after the other up to a count of units,
+//! following a script of waves, or when bought, see [crate::economy].
 use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::Deserialize;
 
+use crate::archetype::*;
+use crate::building::*;
+use crate::economy::SpawnQueue;
+use crate::faction::*;
 use crate::grid::*;
+use crate::sim::*;
+use crate::template::*;
 use crate::unit::*;
+use crate::utils::{Direction, *};
 
 pub struct SpawnPlugin;
 
 impl Plugin for SpawnPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(spawn_info_system);
+        app.add_event::<SpawnEvent>()
+            .add_event::<WaveEvent>()
+            .add_system_to_stage(SimStage, spawn_info_system)
+            .add_system_to_stage(CoreStage::Last, announce_waves);
     }
 }
 
+/// Sent every time a spawner creates a unit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnEvent {
+    pub tick: u64,
+    pub faction: Faction,
+    pub x: i32,
+    pub y: i32,
+}
+
 #[derive(Component)]
 pub struct SpawnInfo {
     pub target_unit_count: Option<u32>,
     pub spawn_delay: Option<f32>,
     pub last_spawn: f32,
-    pub ally: bool,
+    pub faction: Faction,
     pub x: i32,
     pub y: i32,
+    /// Every unit spawned is a copy of it.
+    pub template: UnitTemplate,
 }
 
 impl SpawnInfo {
-    pub fn want_spawn(&self, grid: &Grid, time: &Time, count_of_force: u32) -> bool {
-        let status = grid
-            .get_status(self.x, self.y)
-            .map(|gs| gs == GridStatus::Neutral)
-            .unwrap_or(false);
+    /// Free cell where the next unit appears. A spawner inside a building holds its own cell,
+    /// its units come out on one of the cells around it.
+    pub fn spawn_cell(&self, grid: &Grid, building: bool) -> Option<(i32, i32)> {
+        let free = |(x, y): &(i32, i32)| {
+            grid.get_status(*x, *y) == Some(GridStatus::Neutral) && grid.is_walkable(*x, *y)
+        };
+        if !building {
+            return Some((self.x, self.y)).filter(free);
+        }
+        Direction::iter()
+            .map(|d| (self.x + d.x(), self.y + d.y()))
+            .find(free)
+    }
+
+    pub fn want_spawn(&self, now: f32, count_of_force: u32) -> bool {
         let count = count_of_force < self.target_unit_count.unwrap_or(u32::MAX);
-        let time =
-            self.last_spawn + self.spawn_delay.unwrap_or(0.0) < time.seconds_since_startup() as f32;
-        status && count && time
+        count && self.ready(now)
+    }
+
+    /// The delay since the last unit is over.
+    pub fn ready(&self, now: f32) -> bool {
+        self.last_spawn + self.spawn_delay.unwrap_or(0.0) < now
     }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         &self,
         mut commands: &mut Commands,
         asset_server: &AssetServer,
         mut grid: &mut Grid,
         mut texture_atlas: &mut Assets<TextureAtlas>,
+        archetypes: &Archetypes,
+        (x, y): (i32, i32),
+        template: &UnitTemplate,
         with_spawn: impl FnOnce(&mut EntityCommands),
     ) {
         spawn_unit(
@@ -46,62 +89,327 @@ impl SpawnInfo {
             &asset_server,
             &mut grid,
             &mut texture_atlas,
-            self.x,
-            self.y,
-            self.ally,
-            with_spawn,
+            archetypes,
+            &template.archetype,
+            x,
+            y,
+            self.faction,
+            |c| {
+                template.apply(archetypes, c);
+                with_spawn(c);
+            },
         );
     }
 }
 
+/// Waves of a spawner, as written in the scenario files.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WaveScript {
+    pub list: Vec<Wave>,
+    /// Start again from the first wave after the last one.
+    #[serde(default)]
+    pub repeat: bool,
+    /// Units added to every group on each repeat, 0.5 gives 50% more units each time.
+    #[serde(default)]
+    pub escalation: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Wave {
+    /// Seconds of pause before the wave, from the start of the battle or the end of the
+    /// previous wave.
+    #[serde(default)]
+    pub delay: f32,
+    #[serde(default)]
+    pub boss: bool,
+    pub groups: Vec<WaveGroup>,
+}
+
+/// Units of the same archetype spawned one after the other.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WaveGroup {
+    #[serde(default)]
+    pub archetype: ArchetypeId,
+    pub count: u32,
+    /// Seconds between two units.
+    #[serde(default)]
+    pub interval: f32,
+    /// Without AI, the one of the spawner is used.
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub ai: Option<AiKind>,
+}
+
+/// Sent when a wave starts spawning and once all its units are dead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaveEvent {
+    Started {
+        faction: Faction,
+        /// Waves started by the spawner, counting the repeats.
+        number: u32,
+        boss: bool,
+    },
+    Ended {
+        faction: Faction,
+        number: u32,
+    },
+}
+
+/// Unit sent by a spawner with [SpawnWaves], its wave ends when all of them are dead.
+#[derive(Component)]
+pub struct WaveUnit {
+    pub spawner: Entity,
+}
+
+enum WaveState {
+    /// Pause before the next wave, started at the time given once known.
+    Waiting(Option<f32>),
+    /// The next unit can't come before the time given.
+    Spawning(f32),
+    /// Every unit of the wave is out.
+    Fighting,
+    Finished,
+}
+
+/// Put next to [SpawnInfo] so the spawner follows a script instead of its count of units.
+#[derive(Component)]
+pub struct SpawnWaves {
+    pub script: WaveScript,
+    state: WaveState,
+    number: u32,
+    round: u32,
+    wave: usize,
+    group: usize,
+    spawned: u32,
+}
+
+impl SpawnWaves {
+    pub fn new(script: WaveScript) -> Self {
+        SpawnWaves {
+            script,
+            state: WaveState::Waiting(None),
+            number: 0,
+            round: 0,
+            wave: 0,
+            group: 0,
+            spawned: 0,
+        }
+    }
+
+    /// Units of the group in the current round.
+    pub fn count(&self, group: &WaveGroup) -> u32 {
+        let factor = 1.0 + self.script.escalation * self.round as f32;
+        (group.count as f32 * factor).round() as u32
+    }
+
+    /// Advance the script by a step, `alive` being the count of units sent still alive. Gives
+    /// the group of the unit to spawn, if one must be.
+    pub fn update(
+        &mut self,
+        faction: Faction,
+        now: f32,
+        alive: u32,
+        cell_free: bool,
+        events: &mut Vec<WaveEvent>,
+    ) -> Option<WaveGroup> {
+        match self.state {
+            WaveState::Waiting(since) => {
+                let wave = match self.script.list.get(self.wave) {
+                    Some(wave) => wave,
+                    None => {
+                        self.state = WaveState::Finished;
+                        return None;
+                    }
+                };
+                let since = since.unwrap_or(now);
+                self.state = WaveState::Waiting(Some(since));
+                if now >= since + wave.delay {
+                    self.number += 1;
+                    self.group = 0;
+                    self.spawned = 0;
+                    self.state = WaveState::Spawning(now);
+                    events.push(WaveEvent::Started {
+                        faction,
+                        number: self.number,
+                        boss: wave.boss,
+                    });
+                }
+                None
+            }
+            WaveState::Spawning(next) => {
+                let wave = &self.script.list[self.wave];
+                while let Some(group) = wave.groups.get(self.group) {
+                    if self.spawned < self.count(group) {
+                        break;
+                    }
+                    self.group += 1;
+                    self.spawned = 0;
+                }
+                let group = match wave.groups.get(self.group) {
+                    Some(group) => group,
+                    None => {
+                        self.state = WaveState::Fighting;
+                        return None;
+                    }
+                };
+                if now < next || !cell_free {
+                    return None;
+                }
+                self.spawned += 1;
+                self.state = WaveState::Spawning(now + group.interval);
+                Some(group.clone())
+            }
+            WaveState::Fighting => {
+                if alive > 0 {
+                    return None;
+                }
+                events.push(WaveEvent::Ended {
+                    faction,
+                    number: self.number,
+                });
+                self.wave += 1;
+                if self.wave >= self.script.list.len() && self.script.repeat {
+                    self.wave = 0;
+                    self.round += 1;
+                }
+                self.state = WaveState::Waiting(Some(now));
+                None
+            }
+            WaveState::Finished => None,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn spawn_info_system(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut grid: ResMut<Grid>,
     mut texture_atlas: ResMut<Assets<TextureAtlas>>,
-    time: Res<Time>,
-    mut query: Query<(Entity, &mut SpawnInfo)>,
-    query_of_ai: Query<(
+    archetypes: Res<Archetypes>,
+    clock: Res<SimClock>,
+    mut query: Query<(
         Entity,
-        Option<&TurningAI>,
-        Option<&MoveOnForceAI>,
-        Option<&AttackingAI>,
+        &mut SpawnInfo,
+        Option<&mut SpawnWaves>,
+        Option<&mut SpawnQueue>,
+        Option<&Building>,
     )>,
     count_force: Query<&UnitForce, With<UnitTime>>,
+    wave_units: Query<&WaveUnit>,
+    mut spawn_events: EventWriter<SpawnEvent>,
+    mut wave_events: EventWriter<WaveEvent>,
 ) {
-    let mut good = 0;
-    let mut bad = 0;
+    let mut count_by_faction = [0; Faction::MAX];
 
     for force in count_force.iter() {
-        if force.ally {
-            good += 1;
-        } else {
-            bad += 1;
+        if let Some(count) = count_by_faction.get_mut(force.faction.index()) {
+            *count += 1;
         }
     }
 
-    for (entity, mut si) in query.iter_mut() {
-        let count = if si.ally { good } else { bad };
-        if si.want_spawn(&grid, &time, count) {
-            si.last_spawn = time.seconds_since_startup() as f32;
-            si.spawn(
-                &mut commands,
-                &asset_server,
-                &mut grid,
-                &mut texture_atlas,
-                |c| {
-                    if query_of_ai.get_component::<TurningAI>(entity).is_ok() {
-                        c.insert(TurningAI);
-                    } else if query_of_ai.get_component::<MoveOnForceAI>(entity).is_ok() {
-                        c.insert(MoveOnForceAI::default());
-                    } else if query_of_ai.get_component::<AttackingAI>(entity).is_ok() {
-                        c.insert(AttackingAI);
-                        c.insert(AttackingAIState::MoveToNearestEnemy);
-                    } else {
-                        warn!("No ai found while spawning a new unit");
-                    }
-                },
-            );
+    let mut alive_by_spawner = HashMap::default();
+    for unit in wave_units.iter() {
+        *alive_by_spawner.entry(unit.spawner).or_insert(0) += 1;
+    }
+
+    for (entity, mut si, waves, queue, building) in query.iter_mut() {
+        let now = clock.elapsed();
+        let cell = si.spawn_cell(&grid, building.is_some());
+        let from_wave = waves.is_some();
+        let wave_template;
+        let bought_template;
+        let template = match (waves, queue) {
+            (Some(mut waves), _) => {
+                let alive = alive_by_spawner.get(&entity).cloned().unwrap_or(0);
+                let mut events = Vec::new();
+                let group = waves.update(si.faction, now, alive, cell.is_some(), &mut events);
+                wave_events.send_batch(events.into_iter());
+                wave_template = match group {
+                    Some(group) => UnitTemplate {
+                        archetype: group.archetype,
+                        stats: None,
+                        components: match group.ai {
+                            Some(ai) => ai.components(),
+                            None => si.template.clone_components(),
+                        },
+                    },
+                    None => continue,
+                };
+                &wave_template
+            }
+            // Bought units come out in order, without their count being limited
+            (None, Some(mut queue)) => {
+                if cell.is_none() || !si.ready(now) {
+                    continue;
+                }
+                let faction = si.faction;
+                bought_template = match queue.pop(faction) {
+                    Some(archetype) => UnitTemplate {
+                        archetype,
+                        stats: None,
+                        components: si.template.clone_components(),
+                    },
+                    None => continue,
+                };
+                si.last_spawn = now;
+                &bought_template
+            }
+            (None, None) => {
+                let count = count_by_faction
+                    .get(si.faction.index())
+                    .cloned()
+                    .unwrap_or(0);
+                if cell.is_none() || !si.want_spawn(now, count) {
+                    continue;
+                }
+                si.last_spawn = now;
+                &si.template
+            }
+        };
+
+        let (x, y) = match cell {
+            Some(cell) => cell,
+            None => continue,
+        };
+        spawn_events.send(SpawnEvent {
+            tick: clock.tick(),
+            faction: si.faction,
+            x,
+            y,
+        });
+        si.spawn(
+            &mut commands,
+            &asset_server,
+            &mut grid,
+            &mut texture_atlas,
+            &archetypes,
+            (x, y),
+            template,
+            |c| {
+                if from_wave {
+                    c.insert(WaveUnit { spawner: entity });
+                }
+            },
+        );
+    }
+}
+
+fn announce_waves(mut events: EventReader<WaveEvent>) {
+    for event in events.iter() {
+        match event {
+            WaveEvent::Started {
+                faction,
+                number,
+                boss,
+            } => info!(
+                "Faction {} starts {}wave {}",
+                faction.0,
+                if *boss { "boss " } else { "" },
+                number
+            ),
+            WaveEvent::Ended { faction, number } => {
+                info!("Faction {} ended wave {}", faction.0, number)
+            }
         }
     }
 }
@@ -117,17 +425,19 @@ mod tests {
     mod spawn_something {
         use super::*;
         fn setup_scene(mut commands: Commands) {
-            commands.spawn().insert_bundle((
-                SpawnInfo {
-                    ally: true,
-                    last_spawn: 0.0,
-                    spawn_delay: None,
-                    target_unit_count: Some(3),
-                    x: 1,
-                    y: 1,
-                },
-                MoveOnForceAI::default(),
-            ));
+            commands.spawn().insert(SpawnInfo {
+                faction: Faction(0),
+                last_spawn: 0.0,
+                spawn_delay: None,
+                target_unit_count: Some(3),
+                x: 1,
+                y: 1,
+                template: UnitTemplate::new(ArchetypeId::default()).with_ai(&AiKind::MoveOnForce {
+                    target_x: 0,
+                    target_y: 0,
+                    stick_to_target: false,
+                }),
+            });
         }
 
         fn check_for_spawn(mut count: ResMut<TestCheck<usize>>, query: Query<&UnitStats>) {
@@ -138,6 +448,7 @@ mod tests {
         fn spawn_something() {
             App::new()
                 .add_plugin(Test::Time(1.5))
+                .add_plugin(SimPlugin)
                 .add_plugin(GridPlugin)
                 .add_plugin(UnitPlugin)
                 .add_plugin(AnimPlugin)
@@ -152,6 +463,135 @@ mod tests {
         }
     }
 
+    #[test]
+    #[serial]
+    fn spawned_units_copy_the_template() {
+        fn setup_scene(mut commands: Commands) {
+            commands.spawn().insert(SpawnInfo {
+                faction: Faction(0),
+                last_spawn: 0.0,
+                spawn_delay: None,
+                target_unit_count: Some(1),
+                x: 0,
+                y: 0,
+                template: UnitTemplate::new(ArchetypeId::new("archer"))
+                    .with_stats(Some(StatsOverride {
+                        life: Some(4),
+                        ..Default::default()
+                    }))
+                    .with_ai(&AiKind::MoveOnForce {
+                        target_x: 2,
+                        target_y: 1,
+                        stick_to_target: true,
+                    }),
+            });
+        }
+
+        fn check_unit(
+            mut flag: ResMut<TestCheck<bool>>,
+            query: Query<(&MoveOnForceAI, &UnitStats), Without<AttackingAI>>,
+        ) {
+            **flag = query.iter().any(|(ai, stats)| {
+                (ai.target_x, ai.target_y, ai.stick_to_target) == (2, 1, true)
+                    && stats.life == 4
+                    && stats.is_ranged()
+            });
+        }
+
+        App::new()
+            .add_plugin(Test::Time(0.5))
+            .add_plugin(SimPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(UnitPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(SpawnPlugin)
+            .insert_resource(Grid::new(3, 2))
+            .add_startup_system(init_cameras_2d)
+            .add_startup_system(setup_scene)
+            .insert_resource(TestCheck::new(false).is_true())
+            .add_system(check_unit)
+            .run();
+    }
+
+    #[test]
+    fn waves_follow_their_script() {
+        let group = |count, interval| WaveGroup {
+            archetype: ArchetypeId::default(),
+            count,
+            interval,
+            ai: None,
+        };
+        let mut waves = SpawnWaves::new(WaveScript {
+            list: vec![
+                Wave {
+                    delay: 1.0,
+                    boss: false,
+                    groups: vec![group(2, 0.5)],
+                },
+                Wave {
+                    delay: 0.0,
+                    boss: true,
+                    groups: vec![group(1, 0.0)],
+                },
+            ],
+            repeat: true,
+            escalation: 1.0,
+        });
+        let mut events = Vec::new();
+        let mut update = |waves: &mut SpawnWaves, now, alive| {
+            waves
+                .update(Faction(1), now, alive, true, &mut events)
+                .is_some()
+        };
+
+        assert!(!update(&mut waves, 0.0, 0));
+        assert!(!update(&mut waves, 0.5, 0));
+        assert!(!update(&mut waves, 1.0, 0));
+        assert!(update(&mut waves, 1.0, 0));
+        assert!(!update(&mut waves, 1.2, 1));
+        assert!(update(&mut waves, 1.5, 1));
+        assert!(!update(&mut waves, 2.0, 2));
+        // The wave ends once its units are dead
+        assert!(!update(&mut waves, 2.5, 2));
+        assert!(!update(&mut waves, 3.0, 0));
+        assert!(!update(&mut waves, 3.0, 0));
+        assert!(update(&mut waves, 3.0, 0));
+        assert!(!update(&mut waves, 3.0, 1));
+        assert!(!update(&mut waves, 3.0, 0));
+        // Twice as many units on the second round
+        assert!(!update(&mut waves, 5.0, 0));
+        assert_eq!(waves.count(&group(2, 0.5)), 4);
+
+        assert_eq!(
+            events,
+            vec![
+                WaveEvent::Started {
+                    faction: Faction(1),
+                    number: 1,
+                    boss: false
+                },
+                WaveEvent::Ended {
+                    faction: Faction(1),
+                    number: 1
+                },
+                WaveEvent::Started {
+                    faction: Faction(1),
+                    number: 2,
+                    boss: true
+                },
+                WaveEvent::Ended {
+                    faction: Faction(1),
+                    number: 2
+                },
+                WaveEvent::Started {
+                    faction: Faction(1),
+                    number: 3,
+                    boss: false
+                },
+            ]
+        );
+    }
+
     #[test]
     #[serial]
     fn small_battle() {
@@ -167,38 +607,29 @@ mod tests {
 
     fn battle_of_two_spawners(test: Test, size: i32, delay: f32, units: u32) {
         let setup_scene = move |mut commands: Commands| {
-            commands
-                .spawn()
-                .insert_bundle((
-                    SpawnInfo {
-                        ally: true,
-                        last_spawn: f32::MIN,
-                        spawn_delay: Some(delay),
-                        target_unit_count: Some(units),
-                        x: 0,
-                        y: 0,
-                    },
-                    AttackingAI,
-                ))
-                .insert(AttackingAIState::MoveToNearestEnemy);
-            commands
-                .spawn()
-                .insert_bundle((
-                    SpawnInfo {
-                        ally: false,
-                        last_spawn: f32::MIN,
-                        spawn_delay: Some(delay),
-                        target_unit_count: Some(units),
-                        x: size - 1,
-                        y: size - 1,
-                    },
-                    AttackingAI,
-                ))
-                .insert(AttackingAIState::MoveToNearestEnemy);
+            commands.spawn().insert(SpawnInfo {
+                faction: Faction(0),
+                last_spawn: f32::MIN,
+                spawn_delay: Some(delay),
+                target_unit_count: Some(units),
+                x: 0,
+                y: 0,
+                template: UnitTemplate::new(ArchetypeId::default()).with_ai(&AiKind::Attacking),
+            });
+            commands.spawn().insert(SpawnInfo {
+                faction: Faction(1),
+                last_spawn: f32::MIN,
+                spawn_delay: Some(delay),
+                target_unit_count: Some(units),
+                x: size - 1,
+                y: size - 1,
+                template: UnitTemplate::new(ArchetypeId::default()).with_ai(&AiKind::Attacking),
+            });
         };
 
         App::new()
             .add_plugin(test)
+            .add_plugin(SimPlugin)
             .add_plugin(GridPlugin)
             .add_plugin(UnitPlugin)
             .add_plugin(AnimPlugin)
@@ -0,0 +1,262 @@
+//! Lifecycle of a match: main menu, loading of the scenario, battle, pause and game over.
+//!
+//! The simulation only steps while [AppState::Playing], the battle is checked against the
+//! [VictoryCondition]s of the scenario every frame to know when the match is over.
+use bevy::prelude::*;
+
+use crate::bindings::*;
+use crate::building::Building;
+use crate::button::*;
+use crate::faction::*;
+use crate::grid::*;
+use crate::scenario::*;
+use crate::sim::*;
+use crate::spawn::SpawnInfo;
+use crate::territory::Score;
+use crate::unit::UnitForce;
+use crate::victory::*;
+
+pub struct StatePlugin;
+
+impl Plugin for StatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state(AppState::MainMenu)
+            .init_resource::<Match>()
+            .add_event::<BattleStarted>()
+            .add_event::<RestartBattle>()
+            .add_system(pause_simulation)
+            .add_system(start_match)
+            .add_system(menu_actions)
+            .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(spawn_main_menu))
+            .add_system_set(SystemSet::on_exit(AppState::MainMenu).with_system(despawn_screens))
+            .add_system_set(SystemSet::on_exit(AppState::Controls).with_system(despawn_screens))
+            .add_system_set(SystemSet::on_enter(AppState::Loading).with_system(restart_battle))
+            .add_system_set(
+                SystemSet::on_update(AppState::Playing)
+                    .with_system(end_match)
+                    .with_system(pause_on_key),
+            )
+            .add_system_set(SystemSet::on_enter(AppState::Paused).with_system(spawn_pause_menu))
+            .add_system_set(SystemSet::on_update(AppState::Paused).with_system(pause_on_key))
+            .add_system_set(SystemSet::on_exit(AppState::Paused).with_system(despawn_screens))
+            .add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(spawn_game_over))
+            .add_system_set(SystemSet::on_exit(AppState::GameOver).with_system(despawn_screens));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    MainMenu,
+    /// Rebinding the keys of the actions.
+    Controls,
+    /// Waiting for the scenario to be placed on the grid.
+    Loading,
+    Playing,
+    /// Pushed over [AppState::Playing], the battle is kept as is.
+    Paused,
+    GameOver,
+}
+
+/// Rules and result of the current match.
+#[derive(Default)]
+pub struct Match {
+    pub victory: Vec<VictoryCondition>,
+    pub result: Option<MatchResult>,
+}
+
+/// What a button of the screens does when clicked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub enum MenuAction {
+    Play,
+    Controls,
+    Resume,
+    MainMenu,
+}
+
+/// Everything drawn by a screen, despawned when leaving it.
+#[derive(Component)]
+struct Screen;
+
+fn pause_simulation(state: Res<State<AppState>>, mut clock: ResMut<SimClock>) {
+    if state.is_changed() {
+        clock.paused = *state.current() != AppState::Playing;
+    }
+}
+
+fn restart_battle(mut restart: EventWriter<RestartBattle>) {
+    restart.send(RestartBattle);
+}
+
+/// Take the rules of the battle placed, which ends the loading.
+fn start_match(
+    mut events: EventReader<BattleStarted>,
+    mut state: ResMut<State<AppState>>,
+    mut game: ResMut<Match>,
+) {
+    for event in events.iter() {
+        game.victory = event.victory.clone();
+        game.result = None;
+        if *state.current() == AppState::Loading {
+            set_state(&mut state, AppState::Playing);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn end_match(
+    clock: Res<SimClock>,
+    grid: Res<Grid>,
+    score: Res<Score>,
+    alliances: Res<Alliances>,
+    mut state: ResMut<State<AppState>>,
+    mut game: ResMut<Match>,
+    units: Query<&UnitForce, Without<Building>>,
+    spawners: Query<&SpawnInfo>,
+) {
+    let mut standing = Standing {
+        seconds: clock.elapsed(),
+        cells: grid.cells_by_faction(),
+        walkable_cells: grid.walkable_cells(),
+        score: score.points,
+        ..Default::default()
+    };
+    for force in units.iter() {
+        standing.units[force.faction.index()] += 1;
+    }
+    for spawner in spawners.iter() {
+        standing.spawners[spawner.faction.index()] += 1;
+    }
+
+    if let Some(result) = check_victory(&game.victory, &standing, &alliances) {
+        info!("{} after {:.1}s", result, standing.seconds);
+        game.result = Some(result);
+        set_state(&mut state, AppState::GameOver);
+    }
+}
+
+fn pause_on_key(
+    bindings: Res<Bindings>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<AppState>>,
+) {
+    // Cleared so the new state doesn't see it in the same frame and switch back
+    if !bindings.clear_just_pressed(&mut keys, Action::Pause) {
+        return;
+    }
+    let result = match state.current() {
+        AppState::Playing => state.push(AppState::Paused),
+        _ => state.pop(),
+    };
+    if let Err(e) = result {
+        warn!("Can't pause or resume: {:?}", e);
+    }
+}
+
+fn menu_actions(
+    mut state: ResMut<State<AppState>>,
+    query: Query<(&MenuAction, &Interaction), Changed<Interaction>>,
+) {
+    for (action, interaction) in query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let result = match action {
+            MenuAction::Play => state.set(AppState::Loading),
+            MenuAction::Controls => state.set(AppState::Controls),
+            MenuAction::Resume => state.pop(),
+            MenuAction::MainMenu => state.replace(AppState::MainMenu),
+        };
+        if let Err(e) = result {
+            warn!("Can't do {:?}: {:?}", action, e);
+        }
+    }
+}
+
+fn set_state(state: &mut State<AppState>, next: AppState) {
+    if let Err(e) = state.set(next) {
+        warn!("Can't go to {:?}: {:?}", next, e);
+    }
+}
+
+/// Title over a column of `content` and buttons, on top of the battle.
+pub fn spawn_screen(
+    commands: &mut Commands,
+    spawner: &ButtonSpawner,
+    title: String,
+    content: Vec<Entity>,
+    actions: &[(&str, MenuAction)],
+) {
+    let mut buttons = content;
+    for (text, action) in actions {
+        spawner.spawn_button(commands, text.to_string(), None, |c| {
+            c.insert(*action);
+            buttons.push(c.id());
+        });
+    }
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+            ..Default::default()
+        })
+        .insert(Screen)
+        .with_children(|parent| {
+            parent.spawn_bundle(spawner.text(title, 60.0));
+        })
+        .push_children(&buttons);
+}
+
+fn spawn_main_menu(mut commands: Commands, spawner: Res<ButtonSpawner>) {
+    spawn_screen(
+        &mut commands,
+        &spawner,
+        "Multi warrior".to_string(),
+        Vec::new(),
+        &[
+            ("Play", MenuAction::Play),
+            ("Controls", MenuAction::Controls),
+        ],
+    );
+}
+
+fn spawn_pause_menu(mut commands: Commands, spawner: Res<ButtonSpawner>) {
+    spawn_screen(
+        &mut commands,
+        &spawner,
+        "Paused".to_string(),
+        Vec::new(),
+        &[
+            ("Resume", MenuAction::Resume),
+            ("Main menu", MenuAction::MainMenu),
+        ],
+    );
+}
+
+fn spawn_game_over(mut commands: Commands, spawner: Res<ButtonSpawner>, game: Res<Match>) {
+    let title = match &game.result {
+        Some(result) => result.to_string(),
+        None => "Game over".to_string(),
+    };
+    spawn_screen(
+        &mut commands,
+        &spawner,
+        title,
+        Vec::new(),
+        &[
+            ("Play again", MenuAction::Play),
+            ("Main menu", MenuAction::MainMenu),
+        ],
+    );
+}
+
+fn despawn_screens(mut commands: Commands, screens: Query<Entity, With<Screen>>) {
+    for entity in screens.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -0,0 +1,198 @@
+//! Units described by their components, so spawners can create copies of any kind of unit.
+//!
+//! The components are cloned through reflection, a component only needs to derive `Reflect`
+//! with `#[reflect(Component)]` and to be registered to be usable in a template.
+use bevy::ecs::system::{Command, EntityCommands};
+use bevy::prelude::*;
+use bevy::reflect::TypeRegistryArc;
+use serde::Deserialize;
+
+use crate::archetype::*;
+use crate::damage::*;
+use crate::unit::*;
+
+/// Unit created by a spawner.
+#[derive(Default)]
+pub struct UnitTemplate {
+    pub archetype: ArchetypeId,
+    pub stats: Option<StatsOverride>,
+    /// Cloned on every unit. Without any, the unit gets the AI of its archetype.
+    pub components: Vec<Box<dyn Reflect>>,
+}
+
+impl Clone for UnitTemplate {
+    fn clone(&self) -> Self {
+        UnitTemplate {
+            archetype: self.archetype.clone(),
+            stats: self.stats.clone(),
+            components: self.clone_components(),
+        }
+    }
+}
+
+impl UnitTemplate {
+    pub fn new(archetype: ArchetypeId) -> Self {
+        UnitTemplate {
+            archetype,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_ai(mut self, ai: &AiKind) -> Self {
+        self.components.extend(ai.components());
+        self
+    }
+
+    pub fn with_stats(mut self, stats: Option<StatsOverride>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn clone_components(&self) -> Vec<Box<dyn Reflect>> {
+        self.components
+            .iter()
+            .map(|component| component.clone_value())
+            .collect()
+    }
+
+    /// Give its stats and a copy of the components to a unit spawned from the archetype.
+    pub fn apply(&self, archetypes: &Archetypes, c: &mut EntityCommands) {
+        let archetype = archetypes.get_or_default(&self.archetype);
+        if let Some(overrides) = &self.stats {
+            let mut stats = archetype.stats.clone();
+            overrides.apply(&mut stats);
+            c.insert(stats);
+        }
+        if self.components.is_empty() {
+            archetype.ai.insert(c);
+        }
+        let entity = c.id();
+        c.commands().add(InsertReflected {
+            entity,
+            components: self.clone_components(),
+        });
+    }
+}
+
+/// Stats to change from the ones of the archetype, as written in the scenario files.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct StatsOverride {
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub life: Option<i32>,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub move_speed: Option<f32>,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub damage: Option<i32>,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub damage_type: Option<DamageType>,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub attack_speed: Option<f32>,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub attack_range: Option<i32>,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub armor: Option<i32>,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub resistances: Option<Resistances>,
+    #[serde(default, deserialize_with = "crate::utils::deserialize_some")]
+    pub evasion: Option<f32>,
+}
+
+impl StatsOverride {
+    pub fn apply(&self, stats: &mut UnitStats) {
+        let StatsOverride {
+            life,
+            move_speed,
+            damage,
+            damage_type,
+            attack_speed,
+            attack_range,
+            armor,
+            resistances,
+            evasion,
+        } = self.clone();
+        stats.life = life.unwrap_or(stats.life);
+        stats.move_speed = move_speed.unwrap_or(stats.move_speed);
+        stats.damage = damage.unwrap_or(stats.damage);
+        stats.damage_type = damage_type.unwrap_or(stats.damage_type);
+        stats.attack_speed = attack_speed.unwrap_or(stats.attack_speed);
+        stats.attack_range = attack_range.unwrap_or(stats.attack_range);
+        stats.armor = armor.unwrap_or(stats.armor);
+        stats.resistances = resistances.unwrap_or(stats.resistances);
+        stats.evasion = evasion.unwrap_or(stats.evasion);
+    }
+}
+
+/// Insert components known only through reflection, they need the type registry of the world.
+struct InsertReflected {
+    entity: Entity,
+    components: Vec<Box<dyn Reflect>>,
+}
+
+impl Command for InsertReflected {
+    fn write(self, world: &mut World) {
+        let registry = world.get_resource::<TypeRegistryArc>().unwrap().clone();
+        let registry = registry.read();
+        for component in self.components {
+            let reflect_component = registry
+                .get_with_name(component.type_name())
+                .and_then(|registration| registration.data::<ReflectComponent>());
+            match reflect_component {
+                Some(reflect_component) => {
+                    reflect_component.insert(world, self.entity, &*component)
+                }
+                None => warn!(
+                    "{} is not registered as a component, it can't be in a template",
+                    component.type_name()
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::CommandQueue;
+
+    #[test]
+    fn overrides_only_change_given_stats() {
+        let overrides: StatsOverride =
+            ron::de::from_str("(life: 7, damage_type: Fire, resistances: (fire: 0.5))").unwrap();
+        let mut stats = UnitStats {
+            armor: 2,
+            ..UnitStats::ranged()
+        };
+        overrides.apply(&mut stats);
+        assert_eq!(stats.life, 7);
+        assert_eq!(stats.damage_type, DamageType::Fire);
+        assert_eq!(stats.resistances.fire, 0.5);
+        assert_eq!(stats.armor, 2);
+        assert!(stats.is_ranged());
+    }
+
+    #[test]
+    fn template_inserts_its_reflected_ai() {
+        let mut world = World::new();
+        world.init_resource::<TypeRegistryArc>();
+        world
+            .resource::<TypeRegistryArc>()
+            .write()
+            .register::<MoveOnForceAI>();
+        let template = UnitTemplate::new(ArchetypeId::default()).with_ai(&AiKind::MoveOnForce {
+            target_x: 2,
+            target_y: 1,
+            stick_to_target: true,
+        });
+
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        let mut c = commands.spawn();
+        template.apply(&Archetypes::default(), &mut c);
+        let entity = c.id();
+        queue.apply(&mut world);
+
+        let ai = world.get::<MoveOnForceAI>(entity).unwrap();
+        assert_eq!((ai.target_x, ai.target_y, ai.stick_to_target), (2, 1, true));
+        assert!(world.get::<AttackingAI>(entity).is_none());
+    }
+}
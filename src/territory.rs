@@ -0,0 +1,350 @@
+//! Territory mode: the cells crossed by a faction are painted in its color and stay its own
+//! after its units leave, until an enemy walks on them. Every `interval` seconds, each faction
+//! scores points for its cells.
+//!
+//! ```ron
+//! territory: (interval: 2.0, points: 1, hill: [(4, 4), (5, 4)]),
+//! ```
+//!
+//! With a `hill`, only the cells of the hill score, which gives a king of the hill match.
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::button::ButtonSpawner;
+use crate::faction::*;
+use crate::grid::*;
+use crate::scenario::*;
+use crate::sim::*;
+
+pub struct TerritoryPlugin;
+
+impl Plugin for TerritoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Territory>()
+            .init_resource::<Score>()
+            .add_event::<BattleStarted>()
+            .add_system(start_territory.after(ScenarioSystem::Apply))
+            .add_system_to_stage(SimStage, paint_territory)
+            .add_system_to_stage(SimStage, score_territory);
+    }
+}
+
+/// Painted cells and score readout, for the game with a window.
+pub struct TerritoryHudPlugin;
+
+impl Plugin for TerritoryHudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(spawn_score_hud)
+            .add_system(sync_territory_nodes)
+            .add_system(update_territory_color)
+            .add_system(update_score_hud);
+    }
+}
+
+/// Territory mode of a scenario.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TerritoryDef {
+    /// Seconds between two scores.
+    pub interval: f32,
+    /// Points given for every scoring cell owned.
+    pub points: u32,
+    /// Only these cells score when some are given.
+    pub hill: Vec<(i32, i32)>,
+}
+
+impl Default for TerritoryDef {
+    fn default() -> Self {
+        TerritoryDef {
+            interval: 1.0,
+            points: 1,
+            hill: Vec::new(),
+        }
+    }
+}
+
+/// Owner of every cell, kept after the units left.
+#[derive(Default)]
+pub struct Territory {
+    rules: Option<TerritoryDef>,
+    x: i32,
+    y: i32,
+    owners: Vec<Option<Faction>>,
+    next_score: f32,
+}
+
+impl Territory {
+    pub fn new(grid: &Grid, rules: Option<TerritoryDef>) -> Self {
+        let next_score = rules.as_ref().map(|rules| rules.interval).unwrap_or(0.0);
+        Territory {
+            rules,
+            x: grid.x,
+            y: grid.y,
+            owners: vec![None; (grid.x * grid.y).max(0) as usize],
+            next_score,
+        }
+    }
+
+    fn to_pos(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && x < self.x && y >= 0 && y < self.y {
+            Some((x * self.y + y) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.rules.is_some()
+    }
+
+    pub fn owner(&self, x: i32, y: i32) -> Option<Faction> {
+        self.to_pos(x, y).and_then(|pos| self.owners[pos])
+    }
+
+    pub fn is_hill(&self, x: i32, y: i32) -> bool {
+        match &self.rules {
+            Some(rules) => rules.hill.contains(&(x, y)),
+            None => false,
+        }
+    }
+
+    /// The cells held right now go to the faction holding them.
+    pub fn paint(&mut self, grid: &Grid) {
+        for x in 0..self.x {
+            for y in 0..self.y {
+                if let (Some(pos), Some(faction)) = (self.to_pos(x, y), grid.get_faction(x, y)) {
+                    self.owners[pos] = Some(faction);
+                }
+            }
+        }
+    }
+
+    /// Cells owned by each faction that give points.
+    pub fn scoring_cells(&self) -> [u32; Faction::MAX] {
+        let mut cells = [0; Faction::MAX];
+        let hill = match &self.rules {
+            Some(rules) => rules.hill.as_slice(),
+            None => &[],
+        };
+        for x in 0..self.x {
+            for y in 0..self.y {
+                if !hill.is_empty() && !hill.contains(&(x, y)) {
+                    continue;
+                }
+                if let Some(count) = self
+                    .owner(x, y)
+                    .and_then(|faction| cells.get_mut(faction.index()))
+                {
+                    *count += 1;
+                }
+            }
+        }
+        cells
+    }
+}
+
+/// Points of every faction in the territory mode.
+#[derive(Debug, Default, Clone)]
+pub struct Score {
+    pub points: [u32; Faction::MAX],
+}
+
+fn start_territory(
+    mut events: EventReader<BattleStarted>,
+    grid: Res<Grid>,
+    mut territory: ResMut<Territory>,
+    mut score: ResMut<Score>,
+) {
+    for event in events.iter() {
+        *territory = Territory::new(&grid, event.territory.clone());
+        *score = Score::default();
+    }
+}
+
+fn paint_territory(grid: Res<Grid>, mut territory: ResMut<Territory>) {
+    if territory.is_enabled() {
+        territory.paint(&grid);
+    }
+}
+
+fn score_territory(
+    clock: Res<SimClock>,
+    mut territory: ResMut<Territory>,
+    mut score: ResMut<Score>,
+) {
+    let (interval, points) = match &territory.rules {
+        Some(rules) if rules.interval > 0.0 => (rules.interval, rules.points),
+        _ => return,
+    };
+    while clock.elapsed() >= territory.next_score {
+        territory.next_score += interval;
+        for (total, cells) in score.points.iter_mut().zip(territory.scoring_cells()) {
+            *total += cells * points;
+        }
+    }
+}
+
+#[derive(Component)]
+struct TerritoryNode;
+
+#[derive(Component)]
+struct ScoreHud;
+
+// Over the grid debug nodes, behind the units
+const TERRITORY_NODE_Z: f32 = -0.08;
+
+/// One node per cell, again when the territory changes size.
+fn sync_territory_nodes(
+    mut commands: Commands,
+    territory: Res<Territory>,
+    nodes: Query<Entity, With<TerritoryNode>>,
+) {
+    if !territory.is_changed() {
+        return;
+    }
+    let wanted = if territory.is_enabled() {
+        territory.x * territory.y
+    } else {
+        0
+    };
+    if nodes.iter().len() == wanted.max(0) as usize {
+        return;
+    }
+
+    for node in nodes.iter() {
+        commands.entity(node).despawn();
+    }
+    if !territory.is_enabled() {
+        return;
+    }
+    for x in 0..territory.x {
+        for y in 0..territory.y {
+            commands
+                .spawn_bundle(SpriteBundle::default())
+                .insert(TerritoryNode)
+                .insert(GridLayer(TERRITORY_NODE_Z))
+                .insert(GridTransform::on(x, y));
+        }
+    }
+}
+
+/// Painted cells take a light shade of their owner, the hill cells are darker.
+fn update_territory_color(
+    territory: Res<Territory>,
+    mut nodes: Query<(&GridTransform, &mut Sprite, &mut Visibility), With<TerritoryNode>>,
+) {
+    for (node, mut sprite, mut visibility) in nodes.iter_mut() {
+        let (x, y) = (node.x as i32, node.y as i32);
+        let hill = territory.is_hill(x, y);
+        let color = match territory.owner(x, y) {
+            Some(faction) => {
+                let mut color = faction.color();
+                color.set_a(if hill { 0.6 } else { 0.3 });
+                Some(color)
+            }
+            None if hill => Some(Color::rgba(0.0, 0.0, 0.0, 0.3)),
+            None => None,
+        };
+        if let Some(color) = color {
+            if sprite.color != color {
+                sprite.color = color;
+            }
+        }
+        if visibility.is_visible != color.is_some() {
+            visibility.is_visible = color.is_some();
+        }
+    }
+}
+
+fn spawn_score_hud(mut commands: Commands, spawner: Res<ButtonSpawner>) {
+    let mut text = spawner.text(String::new(), 30.0);
+    text.style = Style {
+        position_type: PositionType::Absolute,
+        position: UiRect {
+            top: Val::Px(10.0),
+            right: Val::Px(10.0),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands.spawn_bundle(text).insert(ScoreHud);
+}
+
+/// A line per faction having points, in its color.
+fn update_score_hud(
+    territory: Res<Territory>,
+    score: Res<Score>,
+    mut query: Query<&mut Text, With<ScoreHud>>,
+) {
+    if !score.is_changed() && !territory.is_changed() {
+        return;
+    }
+    let cells = territory.scoring_cells();
+    for mut text in query.iter_mut() {
+        let style = text.sections[0].style.clone();
+        let mut sections: Vec<TextSection> = Faction::all()
+            .filter(|faction| score.points[faction.index()] > 0 || cells[faction.index()] > 0)
+            .map(|faction| TextSection {
+                value: format!(
+                    "Faction {}: {} points ({} cells)\n",
+                    faction.0,
+                    score.points[faction.index()],
+                    cells[faction.index()]
+                ),
+                style: TextStyle {
+                    color: faction.color(),
+                    ..style.clone()
+                },
+            })
+            .collect();
+        if sections.is_empty() {
+            // Keep a section to know the style next time
+            sections.push(TextSection {
+                value: String::new(),
+                style,
+            });
+        }
+        text.sections = sections;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(hill: Vec<(i32, i32)>) -> Option<TerritoryDef> {
+        Some(TerritoryDef {
+            hill,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn painted_cells_stay_owned() {
+        let mut grid = Grid::new(3, 1);
+        let mut territory = Territory::new(&grid, rules(vec![]));
+        grid.add_unit(0, 0, Faction(1));
+        territory.paint(&grid);
+        grid.change_by_count(0, 0, Faction(1), -1);
+        grid.add_unit(1, 0, Faction(1));
+        territory.paint(&grid);
+        assert_eq!(territory.owner(0, 0), Some(Faction(1)));
+        assert_eq!(territory.owner(2, 0), None);
+
+        grid.add_unit(0, 0, Faction(2));
+        territory.paint(&grid);
+        assert_eq!(territory.owner(0, 0), Some(Faction(2)));
+        assert_eq!(territory.scoring_cells()[1..3], [1, 1]);
+    }
+
+    #[test]
+    fn only_the_hill_scores() {
+        let mut grid = Grid::new(3, 3);
+        let mut territory = Territory::new(&grid, rules(vec![(1, 1)]));
+        grid.add_unit(0, 0, Faction(0));
+        grid.add_unit(1, 1, Faction(1));
+        territory.paint(&grid);
+        assert_eq!(territory.scoring_cells()[0..2], [0, 1]);
+        assert!(territory.is_hill(1, 1));
+    }
+}
@@ -1,12 +1,19 @@
 use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
 
-use rand::*;
+use rand::Rng;
+use serde::Deserialize;
 use std::ops::{Deref, DerefMut};
 
 use crate::anim::*;
+use crate::archetype::*;
+use crate::damage::*;
+use crate::faction::*;
 use crate::fx::*;
 use crate::grid::*;
+use crate::path::*;
+use crate::projectile::*;
+use crate::sim::*;
 use crate::utils::{Direction, *};
 
 #[derive(Default)]
@@ -15,20 +22,97 @@ pub struct UnitPlugin;
 impl Plugin for UnitPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<Events<DamageEvent>>()
-            .add_system(add_time_on_unit_info)
-            .add_system(turning_ai_update)
-            .add_system(move_on_ai_force_update)
-            .add_system(update_attacking_ai)
-            .add_system(damage_event_reader)
-            .add_system(remove_dead_unit);
+            .init_resource::<Alliances>()
+            .init_resource::<UnitIndex>()
+            .init_resource::<Archetypes>()
+            .add_event::<DamageApplied>()
+            .register_type::<TurningAI>()
+            .register_type::<MoveOnForceAI>()
+            .register_type::<AttackingAI>()
+            .register_type::<AttackingAIState>()
+            .add_system_to_stage(SimStage, update_unit_index)
+            .add_system_to_stage(SimStage, add_time_on_unit_info)
+            .add_system_to_stage(SimStage, turning_ai_update)
+            .add_system_to_stage(SimStage, move_on_ai_force_update)
+            .add_system_to_stage(SimStage, update_attacking_ai)
+            .add_system_to_stage(SimStage, follow_unit_orders)
+            .add_system_to_stage(SimStage, damage_event_reader.label(UnitSystem::Damage))
+            .add_system_to_stage(SimStage, remove_dead_unit)
+            .add_system_to_stage(SimStage, invalidate_unit_paths);
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+pub enum UnitSystem {
+    /// Apply the damage to the life of the units, the dead ones are despawned at the end of the
+    /// step.
+    Damage,
+}
+
+#[derive(Debug, Clone)]
 pub struct DamageEvent {
-    pub x: i32,
-    pub y: i32,
-    pub from: bool,
+    pub attacker: Entity,
+    pub target: Entity,
+    pub amount: i32,
+    pub kind: DamageType,
+}
+
+impl DamageEvent {
+    pub fn from_stats(attacker: Entity, stats: &UnitStats, target: Entity) -> Self {
+        DamageEvent {
+            attacker,
+            target,
+            amount: stats.damage,
+            kind: stats.damage_type,
+        }
+    }
+}
+
+/// Units standing on each cell, rebuilt every step so a cell can be turned into the entities
+/// to hit without going through all the units.
+#[derive(Default)]
+pub struct UnitIndex {
+    cells: Vec<Vec<Entity>>,
+    grid_y: i32,
+}
+
+impl UnitIndex {
+    pub fn units_at(&self, x: i32, y: i32) -> &[Entity] {
+        if x < 0 || y < 0 || y >= self.grid_y {
+            return &[];
+        }
+        self.cells
+            .get((x * self.grid_y + y) as usize)
+            .map(|units| units.as_slice())
+            .unwrap_or(&[])
+    }
+
+    fn rebuild(&mut self, grid: &Grid, units: impl Iterator<Item = (Entity, i32, i32)>) {
+        self.grid_y = grid.y;
+        self.cells
+            .resize((grid.x * grid.y).max(0) as usize, Vec::new());
+        for cell in self.cells.iter_mut() {
+            cell.clear();
+        }
+        for (entity, x, y) in units {
+            if let Some(pos) = grid.to_pos(x, y) {
+                self.cells[pos].push(entity);
+            }
+        }
+    }
+}
+
+fn update_unit_index(
+    grid: Res<Grid>,
+    mut index: ResMut<UnitIndex>,
+    query: Query<(Entity, &UnitInfo), With<UnitForce>>,
+) {
+    index.rebuild(
+        &grid,
+        query
+            .iter()
+            .map(|(entity, info)| (entity, info.target_x, info.target_y)),
+    );
 }
 
 #[derive(Default)]
@@ -52,6 +136,7 @@ impl UnitBundle {
                 .insert(self.unit_state.get_animation())
                 .insert(self.unit_state)
                 .insert(UnitTime::default())
+                .insert(UnitPath::default())
                 .insert(GridTransform {
                     x: -1000.0,
                     y: -1000.0,
@@ -63,22 +148,40 @@ impl UnitBundle {
 }
 
 fn damage_event_reader(
+    grid: Res<Grid>,
+    mut rng: ResMut<SimRng>,
     mut damage_events: ResMut<Events<DamageEvent>>,
+    mut applied_events: EventWriter<DamageApplied>,
     mut query: Query<(&UnitInfo, &mut UnitStats)>,
 ) {
     damage_events.update();
     let mut reader = damage_events.get_reader();
 
     for event in reader.iter(&damage_events) {
-        info!("Damage done: {:?}", event);
-        if let Some((_, mut stats)) = query
-            .iter_mut()
-            .find(|(info, _)| info.last_x == event.x && info.last_y == event.y)
-        {
-            stats.life -= 1;
-        } else {
-            info!("Did not find unit to damage");
-        }
+        let (info, mut stats) = match query.get_mut(event.target) {
+            Ok(target) => target,
+            Err(_) => {
+                info!("Did not find unit to damage");
+                continue;
+            }
+        };
+        let applied = resolve_damage(
+            event,
+            &stats,
+            grid.cover(info.last_x, info.last_y),
+            &mut *rng,
+        );
+        info!(
+            "{:?} hits {:?}: {:?}, {} of {} {:?} damage",
+            applied.attacker,
+            applied.target,
+            applied.result,
+            applied.amount,
+            applied.raw,
+            applied.kind
+        );
+        stats.life -= applied.amount;
+        applied_events.send(applied);
     }
 }
 
@@ -91,7 +194,7 @@ fn remove_dead_unit(
     for (entity, stats, force, info, transform) in query.iter() {
         if stats.life <= 0 {
             commands.entity(entity).despawn();
-            grid.change_by_count(info.target_x, info.target_y, -force.as_int());
+            grid.change_by_count(info.target_x, info.target_y, force.faction, -1);
             fx.send(FxSpawnEvent {
                 kind: FxKind::Death,
                 transform: transform.clone(),
@@ -101,17 +204,16 @@ fn remove_dead_unit(
     }
 }
 
-fn add_time_on_unit_info(time: Res<Time>, mut query: Query<&mut UnitTime>) {
-    let delta = time.delta_seconds();
-    query.par_for_each_mut(64, |mut unit| {
-        unit.time += delta;
-    });
+fn add_time_on_unit_info(clock: Res<SimClock>, mut query: Query<&mut UnitTime>) {
+    for mut unit in query.iter_mut() {
+        unit.time += clock.step;
+    }
 }
 
 fn turning_ai_update(
     mut query: Query<
         (&UnitTime, &mut UnitState, &mut UnitInfo, &mut GridTransform),
-        With<TurningAI>,
+        (With<TurningAI>, Without<UnitOrder>),
     >,
 ) {
     for (unit_time, mut state, mut info, mut transform) in query.iter_mut() {
@@ -139,6 +241,7 @@ fn turning_ai_update(
 
 fn find_potential_pos(
     grid: &Grid,
+    rng: &mut impl Rng,
     cur_x: i32,
     cur_y: i32,
     target_x: i32,
@@ -150,13 +253,16 @@ fn find_potential_pos(
     for d in Direction::iter() {
         let x = cur_x + d.x();
         let y = cur_y + d.y();
+        if !grid.is_walkable(x, y) {
+            continue;
+        }
         if let Some(status) = grid.get_status(x, y) {
             if status == status_wanted || status == GridStatus::Neutral {
                 let distance = (target_x - x).abs() + (target_y - y).abs();
                 if distance < pos_distance {
                     potential_pos = Some((d, x, y));
                     pos_distance = distance;
-                } else if distance == pos_distance && random::<bool>() {
+                } else if distance == pos_distance && rng.gen::<bool>() {
                     potential_pos = Some((d, x, y));
                 }
             }
@@ -165,35 +271,67 @@ fn find_potential_pos(
     potential_pos
 }
 
-fn grid_info_move_to(grid: &mut Grid, mut info: &mut UnitInfo, x: i32, y: i32, ally: bool) {
-    let count_change = if ally { 1 } else { -1 };
-    grid.change_by_count(info.last_x, info.last_y, -count_change);
-    grid.change_by_count(x, y, count_change);
+/// Follow the cached path to the target, falling back on a greedy step when no path exists.
+#[allow(clippy::too_many_arguments)]
+fn next_pos(
+    grid: &Grid,
+    rng: &mut impl Rng,
+    path: &mut UnitPath,
+    cur_x: i32,
+    cur_y: i32,
+    target_x: i32,
+    target_y: i32,
+    status_wanted: GridStatus,
+) -> Option<(Direction, i32, i32)> {
+    let step = path.next_step(grid, cur_x, cur_y, target_x, target_y, status_wanted);
+    if step.is_none() && !path.is_reachable() {
+        find_potential_pos(grid, rng, cur_x, cur_y, target_x, target_y, status_wanted)
+    } else {
+        step
+    }
+}
+
+/// Time needed to walk into the cell, slowed down by its terrain.
+fn move_duration(grid: &Grid, stats: &UnitStats, delay: f32, x: i32, y: i32) -> f32 {
+    let cost = grid.movement_cost(x, y).unwrap_or(1) as f32;
+    delay * cost / stats.move_speed
+}
+
+fn grid_info_move_to(grid: &mut Grid, info: &mut UnitInfo, x: i32, y: i32, faction: Faction) {
+    grid.change_by_count(info.last_x, info.last_y, faction, -1);
+    grid.change_by_count(x, y, faction, 1);
     info.target_x = x;
     info.target_y = y;
 }
 
 fn move_on_ai_force_update(
     mut grid: ResMut<Grid>,
-    mut query: Query<(
-        &UnitTime,
-        &UnitStats,
-        &mut UnitState,
-        &mut UnitInfo,
-        &mut GridTransform,
-        &mut MoveOnForceAI,
-        &UnitForce,
-    )>,
+    mut rng: ResMut<SimRng>,
+    mut query: Query<
+        (
+            &UnitTime,
+            &UnitStats,
+            &mut UnitState,
+            &mut UnitInfo,
+            &mut GridTransform,
+            &mut MoveOnForceAI,
+            &mut UnitPath,
+            &UnitForce,
+        ),
+        Without<UnitOrder>,
+    >,
 ) {
-    for (unit_time, stats, mut state, mut info, mut transform, mut ai, force) in query.iter_mut() {
+    for (unit_time, stats, mut state, mut info, mut transform, mut ai, mut path, force) in
+        query.iter_mut()
+    {
         update_pos(&unit_time, &info, &mut transform);
 
         if state.is_still() && info.last_x == ai.target_x && info.last_y == ai.target_y {
             if ai.stick_to_target {
                 continue;
             } else {
-                ai.target_x = random::<i32>().abs() % grid.x;
-                ai.target_y = random::<i32>().abs() % grid.y;
+                ai.target_x = rng.gen_range(0..grid.x);
+                ai.target_y = rng.gen_range(0..grid.y);
                 info!("Target {} {}", ai.target_x, ai.target_y);
             }
         }
@@ -204,8 +342,10 @@ fn move_on_ai_force_update(
             let status_wanted = force.as_grid_status();
             *state = match &*state {
                 UnitState::Still(dir) => {
-                    let potential_pos = find_potential_pos(
+                    let potential_pos = next_pos(
                         &grid,
+                        &mut *rng,
+                        &mut path,
                         info.last_x,
                         info.last_y,
                         ai.target_x,
@@ -214,8 +354,9 @@ fn move_on_ai_force_update(
                     );
 
                     if let Some((d, x, y)) = potential_pos {
-                        grid_info_move_to(&mut grid, &mut info, x, y, force.ally);
-                        info.end_time = unit_time.time + (info.action_delay / stats.move_speed);
+                        grid_info_move_to(&mut grid, &mut info, x, y, force.faction);
+                        info.end_time =
+                            unit_time.time + move_duration(&grid, stats, info.action_delay, x, y);
                         UnitState::Moving(d)
                     } else {
                         UnitState::Still(dir.next())
@@ -257,12 +398,22 @@ pub struct UnitTime {
     pub time: f32,
 }
 
-#[derive(Component)]
+/// Read from the archetype files, where every missing value is the default one.
+#[derive(Debug, Clone, Component, Deserialize)]
+#[serde(default)]
 pub struct UnitStats {
     pub life: i32,
     pub move_speed: f32,
     pub damage: i32,
+    pub damage_type: DamageType,
     pub attack_speed: f32,
+    /// Distance in cells from which the unit attacks, farther than 1 it shoots projectiles.
+    pub attack_range: i32,
+    /// Removed from every physical hit.
+    pub armor: i32,
+    pub resistances: Resistances,
+    /// Chance to dodge a hit, between 0 and 1.
+    pub evasion: f32,
 }
 
 impl Default for UnitStats {
@@ -272,60 +423,151 @@ impl Default for UnitStats {
             attack_speed: 1.0,
             life: 1,
             damage: 1,
+            damage_type: DamageType::Physical,
+            attack_range: 1,
+            armor: 0,
+            resistances: Resistances::default(),
+            evasion: 0.0,
+        }
+    }
+}
+
+impl UnitStats {
+    /// Fragile unit throwing fire balls from afar.
+    pub fn ranged() -> Self {
+        Self {
+            attack_range: 4,
+            attack_speed: 0.5,
+            damage_type: DamageType::Fire,
+            ..Default::default()
         }
     }
+
+    pub fn is_ranged(&self) -> bool {
+        self.attack_range > 1
+    }
 }
 
 #[derive(Component)]
 pub struct UnitForce {
-    pub ally: bool,
+    pub faction: Faction,
 }
 
 impl UnitForce {
-    pub fn as_int(&self) -> i32 {
-        if self.ally {
-            1
-        } else {
-            -1
-        }
-    }
-
     pub fn as_grid_status(&self) -> GridStatus {
-        if self.ally {
-            GridStatus::Friend
-        } else {
-            GridStatus::Enemy
-        }
+        GridStatus::Held(self.faction)
     }
 }
 
-#[derive(Component)]
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
 pub struct TurningAI;
 
-#[derive(Default, Component)]
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
 pub struct MoveOnForceAI {
     pub target_x: i32,
     pub target_y: i32,
     pub stick_to_target: bool,
 }
 
-#[derive(Component)]
+#[derive(Default, Component, Reflect)]
+#[reflect(Component)]
 pub struct AttackingAI;
 
-#[derive(Debug, Component)]
+/// AI given to a unit, as written in the scenario files.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub enum AiKind {
+    Turning,
+    MoveOnForce {
+        target_x: i32,
+        target_y: i32,
+        #[serde(default)]
+        stick_to_target: bool,
+    },
+    #[default]
+    Attacking,
+}
+
+impl AiKind {
+    /// Components of the AI, to put in a [crate::template::UnitTemplate].
+    pub fn components(&self) -> Vec<Box<dyn Reflect>> {
+        match self.clone() {
+            AiKind::Turning => vec![Box::new(TurningAI)],
+            AiKind::MoveOnForce {
+                target_x,
+                target_y,
+                stick_to_target,
+            } => vec![Box::new(MoveOnForceAI {
+                target_x,
+                target_y,
+                stick_to_target,
+            })],
+            AiKind::Attacking => vec![
+                Box::new(AttackingAI),
+                Box::new(AttackingAIState::MoveToNearestEnemy),
+            ],
+        }
+    }
+
+    pub fn insert(&self, c: &mut EntityCommands) {
+        match self.clone() {
+            AiKind::Turning => {
+                c.insert(TurningAI);
+            }
+            AiKind::MoveOnForce {
+                target_x,
+                target_y,
+                stick_to_target,
+            } => {
+                c.insert(MoveOnForceAI {
+                    target_x,
+                    target_y,
+                    stick_to_target,
+                });
+            }
+            AiKind::Attacking => {
+                c.insert(AttackingAI)
+                    .insert(AttackingAIState::MoveToNearestEnemy);
+            }
+        }
+    }
+}
+
+/// Order given by a player to the unit, its AI waits until the order is done.
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub enum UnitOrder {
+    MoveTo {
+        x: i32,
+        y: i32,
+    },
+    /// Chase the unit and hit it until it dies.
+    Attack(Entity),
+}
+
+#[derive(Debug, Default, Clone, Component, Reflect)]
+#[reflect_value(Component)]
 pub enum AttackingAIState {
     PrepareAttack,
     AfterAttack,
+    #[default]
     MoveToNearestEnemy,
 }
 
-fn find_enemy_in_range(grid: &Grid, x: i32, y: i32, ally: bool, range: i32) -> Vec<(i32, i32)> {
+fn find_enemy_in_range(
+    grid: &Grid,
+    alliances: &Alliances,
+    x: i32,
+    y: i32,
+    faction: Faction,
+    range: i32,
+) -> Vec<(i32, i32)> {
     let mut result = Vec::new();
     let mut push = |(n_x, n_y)| {
-        if ally && grid.get_status(n_x, n_y) == Some(GridStatus::Enemy) {
-            result.push((n_x, n_y));
-        } else if !ally && grid.get_status(n_x, n_y) == Some(GridStatus::Friend) {
-            result.push((n_x, n_y))
+        if let Some(other) = grid.get_faction(n_x, n_y) {
+            if alliances.are_hostile(faction, other) {
+                result.push((n_x, n_y));
+            }
         }
     };
 
@@ -346,50 +588,145 @@ fn find_enemy_in_range(grid: &Grid, x: i32, y: i32, ally: bool, range: i32) -> V
 }
 #[test]
 fn find_enemy_in_corner() {
+    let alliances = Alliances::default();
     let mut grid = Grid::new(4, 4);
-    grid.add_friend(0, 0);
-    grid.add_enemy(3, 3);
-
-    assert_eq!(find_enemy_in_range(&grid, 0, 0, true, 10), vec![(3, 3)]);
-    assert_eq!(find_enemy_in_range(&grid, 3, 3, false, 10), vec![(0, 0)]);
+    grid.add_unit(0, 0, Faction(0));
+    grid.add_unit(3, 3, Faction(1));
+
+    assert_eq!(
+        find_enemy_in_range(&grid, &alliances, 0, 0, Faction(0), 10),
+        vec![(3, 3)]
+    );
+    assert_eq!(
+        find_enemy_in_range(&grid, &alliances, 3, 3, Faction(1), 10),
+        vec![(0, 0)]
+    );
 }
 
 #[test]
 fn enemy_in_range() {
+    let alliances = Alliances::default();
+    let ally = Faction(0);
+    let enemy = Faction(1);
     let mut grid = Grid::new(2, 2);
 
-    grid.add_enemy(1, 1);
-
-    assert_eq!(find_enemy_in_range(&grid, 1, 1, true, 1), vec![]);
-    assert_eq!(find_enemy_in_range(&grid, 1, 1, false, 1), vec![]);
-    assert_eq!(find_enemy_in_range(&grid, 0, 0, false, 1), vec![]);
-
-    assert_eq!(find_enemy_in_range(&grid, 1, 0, true, 1), vec![(1, 1)]);
-    assert_eq!(find_enemy_in_range(&grid, 0, 1, true, 1), vec![(1, 1)]);
+    grid.add_unit(1, 1, enemy);
+
+    assert_eq!(
+        find_enemy_in_range(&grid, &alliances, 1, 1, ally, 1),
+        vec![]
+    );
+    assert_eq!(
+        find_enemy_in_range(&grid, &alliances, 1, 1, enemy, 1),
+        vec![]
+    );
+    assert_eq!(
+        find_enemy_in_range(&grid, &alliances, 0, 0, enemy, 1),
+        vec![]
+    );
+
+    assert_eq!(
+        find_enemy_in_range(&grid, &alliances, 1, 0, ally, 1),
+        vec![(1, 1)]
+    );
+    assert_eq!(
+        find_enemy_in_range(&grid, &alliances, 0, 1, ally, 1),
+        vec![(1, 1)]
+    );
     grid = Grid::new(5, 5);
-    grid.add_friend(3, 3);
+    grid.add_unit(3, 3, ally);
     for i in 0..6 {
-        assert_eq!(find_enemy_in_range(&grid, 0, 0, false, i), vec![]);
+        assert_eq!(
+            find_enemy_in_range(&grid, &alliances, 0, 0, enemy, i),
+            vec![]
+        );
     }
 
     for i in 6..25 {
-        assert_eq!(find_enemy_in_range(&grid, 0, 0, false, i), vec![(3, 3)]);
+        assert_eq!(
+            find_enemy_in_range(&grid, &alliances, 0, 0, enemy, i),
+            vec![(3, 3)]
+        );
     }
 
-    grid.add_friend(3, 4);
-    grid.add_friend(4, 3);
+    grid.add_unit(3, 4, ally);
+    grid.add_unit(4, 3, ally);
 
-    let mut final_test = find_enemy_in_range(&grid, 0, 0, false, 8);
+    let mut final_test = find_enemy_in_range(&grid, &alliances, 0, 0, enemy, 8);
     final_test.sort();
     assert_eq!(final_test, vec![(3, 3), (3, 4), (4, 3)]);
 }
 
+#[test]
+fn allied_factions_are_not_enemies() {
+    let alliances = Alliances::from_teams(&[0, 0, 1, 1]);
+    let mut grid = Grid::new(3, 3);
+    grid.add_unit(0, 1, Faction(1));
+    grid.add_unit(2, 1, Faction(2));
+    grid.add_unit(1, 2, Faction(5));
+
+    let mut enemies = find_enemy_in_range(&grid, &alliances, 1, 1, Faction(0), 1);
+    enemies.sort();
+    assert_eq!(enemies, vec![(1, 2), (2, 1)]);
+    let mut enemies = find_enemy_in_range(&grid, &alliances, 1, 1, Faction(3), 1);
+    enemies.sort();
+    assert_eq!(enemies, vec![(0, 1), (1, 2)]);
+}
+
+#[test]
+fn unit_index_finds_units_by_cell() {
+    let grid = Grid::new(3, 2);
+    let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+    let mut index = UnitIndex::default();
+    index.rebuild(&grid, vec![(a, 0, 1), (b, 2, 1), (b, 5, 5)].into_iter());
+    assert_eq!(index.units_at(0, 1), &[a]);
+    assert_eq!(index.units_at(2, 1), &[b]);
+    assert!(index.units_at(1, 1).is_empty());
+    assert!(index.units_at(0, 2).is_empty());
+
+    index.rebuild(&grid, vec![(a, 2, 1), (b, 2, 1)].into_iter());
+    assert!(index.units_at(0, 1).is_empty());
+    assert_eq!(index.units_at(2, 1), &[a, b]);
+}
+
+/// Hit the target from the cell of the unit: a projectile for the ranged units, the damage right
+/// away otherwise.
+#[allow(clippy::too_many_arguments)]
+fn attack(
+    commands: &mut Commands,
+    fx_events: &mut Events<FxSpawnEvent>,
+    damage_events: &mut Events<DamageEvent>,
+    entity: Entity,
+    stats: &UnitStats,
+    info: &UnitInfo,
+    trans: &Transform,
+    target: Entity,
+) {
+    let damage = DamageEvent::from_stats(entity, stats, target);
+    if stats.is_ranged() {
+        Projectile::spawn(commands, info.last_x, info.last_y, damage, trans);
+        fx_events.send(FxSpawnEvent {
+            kind: FxKind::Muzzle,
+            transform: *trans,
+            duration: Some(0.5),
+        });
+    } else {
+        damage_events.send(damage);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn update_attacking_ai(
+    mut commands: Commands,
     mut grid: ResMut<Grid>,
+    mut rng: ResMut<SimRng>,
+    alliances: Res<Alliances>,
+    index: Res<UnitIndex>,
     mut damage_events: ResMut<Events<DamageEvent>>,
     mut fx_events: ResMut<Events<FxSpawnEvent>>,
     mut query: Query<
         (
+            Entity,
             &mut AttackingAIState,
             &mut UnitInfo,
             &UnitStats,
@@ -397,13 +734,24 @@ pub fn update_attacking_ai(
             &UnitForce,
             &mut UnitState,
             &mut GridTransform,
+            &mut UnitPath,
             &Transform,
         ),
-        With<AttackingAI>,
+        (With<AttackingAI>, Without<UnitOrder>),
     >,
 ) {
-    for (mut state, mut info, stats, time, force, mut anim_state, mut transform, trans) in
-        query.iter_mut()
+    for (
+        entity,
+        mut state,
+        mut info,
+        stats,
+        time,
+        force,
+        mut anim_state,
+        mut transform,
+        mut path,
+        trans,
+    ) in query.iter_mut()
     {
         update_pos(&time, &info, &mut transform);
 
@@ -412,21 +760,35 @@ pub fn update_attacking_ai(
             continue;
         }
 
-        // Find an enemy that is 1 cell away since it is useful in all cases
-        let enemy_close = find_enemy_in_range(&grid, info.last_x, info.last_y, force.ally, 1)
-            .iter()
-            .next()
-            .cloned();
+        // Find an enemy in attack range since it is useful in all cases
+        let enemy_close = find_enemy_in_range(
+            &grid,
+            &alliances,
+            info.last_x,
+            info.last_y,
+            force.faction,
+            stats.attack_range,
+        )
+        .iter()
+        .next()
+        .cloned();
 
         // Find the next state
         let new_state = match *state {
             AttackingAIState::PrepareAttack => {
-                if let Some((enemy_x, enemy_y)) = enemy_close {
-                    damage_events.send(DamageEvent {
-                        x: enemy_x,
-                        y: enemy_y,
-                        from: force.ally,
-                    });
+                let target = enemy_close
+                    .and_then(|(enemy_x, enemy_y)| index.units_at(enemy_x, enemy_y).first());
+                if let Some(target) = target {
+                    attack(
+                        &mut commands,
+                        &mut fx_events,
+                        &mut damage_events,
+                        entity,
+                        stats,
+                        &info,
+                        trans,
+                        *target,
+                    );
                     AttackingAIState::AfterAttack
                 } else {
                     AttackingAIState::MoveToNearestEnemy
@@ -478,21 +840,29 @@ pub fn update_attacking_ai(
             AttackingAIState::AfterAttack => (1.0, UnitState::Still(Direction::Down)),
 
             AttackingAIState::MoveToNearestEnemy => {
-                if let Some((enemy_x, enemy_y)) =
-                    find_enemy_in_range(&grid, info.last_x, info.last_y, force.ally, 1000)
-                        .iter()
-                        .next()
+                if let Some((enemy_x, enemy_y)) = find_enemy_in_range(
+                    &grid,
+                    &alliances,
+                    info.last_x,
+                    info.last_y,
+                    force.faction,
+                    1000,
+                )
+                .iter()
+                .next()
                 {
-                    if let Some((d, x, y)) = find_potential_pos(
+                    if let Some((d, x, y)) = next_pos(
                         &grid,
+                        &mut *rng,
+                        &mut path,
                         info.last_x,
                         info.last_y,
                         enemy_x.clone(),
                         enemy_y.clone(),
                         GridStatus::Neutral,
                     ) {
-                        grid_info_move_to(&mut grid, &mut info, x, y, force.ally);
-                        (1.0 / stats.move_speed, UnitState::Moving(d))
+                        grid_info_move_to(&mut grid, &mut info, x, y, force.faction);
+                        (move_duration(&grid, stats, 1.0, x, y), UnitState::Moving(d))
                     } else {
                         (1.0, UnitState::Still(Direction::Down))
                     }
@@ -513,37 +883,177 @@ pub fn update_attacking_ai(
     }
 }
 
+/// Units with a [UnitOrder] walk to its cell or chase its target, then go back to their AI. An
+/// order that can't be done anymore, blocked, out of reach or with a dead target, is dropped.
+fn follow_unit_orders(
+    mut commands: Commands,
+    mut grid: ResMut<Grid>,
+    mut damage_events: ResMut<Events<DamageEvent>>,
+    mut fx_events: ResMut<Events<FxSpawnEvent>>,
+    mut units: ParamSet<(
+        Query<&UnitInfo>,
+        Query<(
+            Entity,
+            &UnitOrder,
+            &mut UnitInfo,
+            &UnitStats,
+            &UnitTime,
+            &UnitForce,
+            &mut UnitState,
+            &mut GridTransform,
+            &mut UnitPath,
+            &Transform,
+        )>,
+    )>,
+) {
+    let orders: Vec<(Entity, UnitOrder)> = units
+        .p1()
+        .iter()
+        .map(|(entity, order, ..)| (entity, *order))
+        .collect();
+    // Cell to go to for every order, the targets still alive
+    let goals: Vec<(Entity, (i32, i32))> = {
+        let infos = units.p0();
+        orders
+            .iter()
+            .filter_map(|(entity, order)| match order {
+                UnitOrder::MoveTo { x, y } => Some((*entity, (*x, *y))),
+                UnitOrder::Attack(target) => infos
+                    .get(*target)
+                    .ok()
+                    .map(|info| (*entity, (info.target_x, info.target_y))),
+            })
+            .collect()
+    };
+
+    let mut query = units.p1();
+    for (entity, order, mut info, stats, time, force, mut state, mut transform, mut path, trans) in
+        query.iter_mut()
+    {
+        let goal = goals
+            .iter()
+            .find(|(unit, _)| *unit == entity)
+            .map(|(_, goal)| *goal);
+        let (goal_x, goal_y) = match goal {
+            Some(goal) => goal,
+            None => {
+                commands.entity(entity).remove::<UnitOrder>();
+                continue;
+            }
+        };
+        update_pos(time, &info, &mut transform);
+        if info.end_time > time.time {
+            continue;
+        }
+        info.start_time = time.time;
+        if let UnitState::Moving(dir) = *state {
+            info.last_x = info.target_x;
+            info.last_y = info.target_y;
+            info.end_time = time.time;
+            *state = UnitState::Still(dir);
+            continue;
+        }
+
+        let distance = (goal_x - info.last_x).abs() + (goal_y - info.last_y).abs();
+        let (status_wanted, done) = match order {
+            UnitOrder::MoveTo { .. } => (force.as_grid_status(), distance == 0),
+            UnitOrder::Attack(_) => (GridStatus::Neutral, false),
+        };
+        if done {
+            commands.entity(entity).remove::<UnitOrder>();
+            continue;
+        }
+        if let UnitOrder::Attack(target) = order {
+            if distance <= stats.attack_range {
+                attack(
+                    &mut commands,
+                    &mut fx_events,
+                    &mut damage_events,
+                    entity,
+                    stats,
+                    &info,
+                    trans,
+                    *target,
+                );
+                info.end_time = time.time + 1.0 / stats.attack_speed;
+                *state = UnitState::Still(Direction::from_points(
+                    info.last_x,
+                    info.last_y,
+                    goal_x,
+                    goal_y,
+                ));
+                continue;
+            }
+        }
+
+        let step = path.next_step(
+            &grid,
+            info.last_x,
+            info.last_y,
+            goal_x,
+            goal_y,
+            status_wanted,
+        );
+        // Walled off, searching again on every step would never get there
+        if step.is_none() && !path.is_reachable() {
+            commands.entity(entity).remove::<UnitOrder>();
+            continue;
+        }
+        match (step, order) {
+            (Some((d, x, y)), _) => {
+                grid_info_move_to(&mut grid, &mut info, x, y, force.faction);
+                info.end_time = time.time + move_duration(&grid, stats, info.action_delay, x, y);
+                *state = UnitState::Moving(d);
+            }
+            (None, UnitOrder::MoveTo { .. }) => {
+                commands.entity(entity).remove::<UnitOrder>();
+            }
+            // The way to the target may open again when it moves
+            (None, UnitOrder::Attack(_)) => info.end_time = time.time + 1.0,
+        }
+    }
+}
+
+/// Spawn a unit with the look and the stats of its archetype. Its AI is left to `with_unit`.
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_unit<'a, G, TA>(
     commands: &'a mut Commands,
     asset_server: &impl Deref<Target = AssetServer>,
     grid: &mut G,
     texture_atlases: &mut TA,
+    archetypes: &Archetypes,
+    archetype: &ArchetypeId,
     x: i32,
     y: i32,
-    ally: bool,
+    faction: Faction,
     with_unit: impl FnOnce(&mut EntityCommands<'_, '_, '_>),
 ) where
     G: Deref<Target = Grid> + DerefMut,
     TA: Deref<Target = Assets<TextureAtlas>> + DerefMut,
 {
-    let path = if ally {
-        "spritesheet/Female/Female 12-3.png"
-    } else {
-        "spritesheet/Soldier/Soldier 01-1.png"
-    };
-    let texture_handle = asset_server.load(path);
-    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(32.0, 32.0), 3, 4);
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
     grid.get_count(x, y)
         .expect("Expected valid position for the new unit");
-    grid.change_by_count(x, y, if ally { 1 } else { -1 });
+    // The grid would not count the unit, it could never leave the cell
+    if let Some(holder) = grid.get_faction(x, y) {
+        if holder != faction {
+            warn!(
+                "{:?} can't spawn on ({}, {}) held by {:?}",
+                faction, x, y, holder
+            );
+            return;
+        }
+    }
+    grid.change_by_count(x, y, faction, 1);
+    let kind = archetypes.get_or_default(archetype);
+    let texture_atlas = kind.texture_atlas(asset_server, faction.spritesheet());
+    let texture_atlas_handle = texture_atlases.add(texture_atlas);
 
     UnitBundle {
         spritesheet: SpriteSheetBundle {
             texture_atlas: texture_atlas_handle.clone(),
             transform: Transform {
                 translation: Vec3::splat(-100.0),
-                scale: Vec3::splat(3.0),
+                scale: Vec3::splat(kind.layout.scale),
                 ..Default::default()
             },
             ..Default::default()
@@ -558,10 +1068,10 @@ pub fn spawn_unit<'a, G, TA>(
             end_time: f32::MIN,
         },
         unit_state: UnitState::Moving(crate::unit::Direction::Right),
-        unit_stats: Default::default(),
+        unit_stats: kind.stats.clone(),
     }
     .build(commands, |c| {
-        c.insert(UnitForce { ally: ally });
+        c.insert(UnitForce { faction }).insert(archetype.clone());
         with_unit(c);
     })
 }
@@ -591,16 +1101,19 @@ mod tests {
             asset_server: ResMut<AssetServer>,
             mut grid: ResMut<Grid>,
             mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
         ) {
-            grid.add_enemy(1, 0);
+            grid.add_unit(1, 0, Faction(1));
             spawn_unit(
                 &mut commands,
                 &asset_server,
                 &mut grid,
                 &mut texture_atlases,
+                &archetypes,
+                &ArchetypeId::default(),
                 0,
                 0,
-                true,
+                Faction(0),
                 |c| {
                     c.insert(MoveOnForceAI::default());
                 },
@@ -608,6 +1121,7 @@ mod tests {
         }
         App::new()
             .add_plugin(Test::Frames(10))
+            .add_plugin(SimPlugin)
             .add_plugin(GridPlugin)
             .add_plugin(FxPlugin)
             .add_plugin(UnitPlugin)
@@ -626,16 +1140,19 @@ mod tests {
             asset_server: ResMut<AssetServer>,
             mut grid: ResMut<Grid>,
             mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
         ) {
-            grid.add_friend(0, 1);
+            grid.add_unit(0, 1, Faction(0));
             spawn_unit(
                 &mut commands,
                 &asset_server,
                 &mut grid,
                 &mut texture_atlases,
+                &archetypes,
+                &ArchetypeId::default(),
                 0,
                 0,
-                false,
+                Faction(1),
                 |c| {
                     c.insert(MoveOnForceAI::default());
                 },
@@ -643,6 +1160,7 @@ mod tests {
         }
         App::new()
             .add_plugin(Test::Frames(10))
+            .add_plugin(SimPlugin)
             .add_plugin(GridPlugin)
             .add_plugin(FxPlugin)
             .add_plugin(UnitPlugin)
@@ -653,6 +1171,46 @@ mod tests {
             .run();
     }
 
+    #[test]
+    #[serial]
+    fn unit_is_not_spawned_on_an_enemy_cell() {
+        fn init(
+            mut commands: Commands,
+            asset_server: ResMut<AssetServer>,
+            mut grid: ResMut<Grid>,
+            mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
+        ) {
+            for faction in [Faction(0), Faction(1)] {
+                spawn_unit(
+                    &mut commands,
+                    &asset_server,
+                    &mut grid,
+                    &mut texture_atlases,
+                    &archetypes,
+                    &ArchetypeId::default(),
+                    0,
+                    0,
+                    faction,
+                    |_| {},
+                );
+            }
+            assert_eq!(grid.get_count(0, 0), Some(1));
+            assert_eq!(grid.get_faction(0, 0), Some(Faction(0)));
+        }
+        App::new()
+            .add_plugin(Test::Frames(3))
+            .add_plugin(SimPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(UnitPlugin)
+            .add_system(init_cameras_2d)
+            .insert_resource(Grid::new(1, 1))
+            .add_startup_system(init)
+            .add_system(assert_stay_on_0_0)
+            .run();
+    }
+
     #[test]
     #[ignore]
     #[serial]
@@ -662,15 +1220,18 @@ mod tests {
             asset_server: Res<AssetServer>,
             mut grid: ResMut<Grid>,
             mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
         ) {
             spawn_unit(
                 &mut commands,
                 &asset_server,
                 &mut grid,
                 &mut texture_atlases,
+                &archetypes,
+                &ArchetypeId::default(),
                 0,
                 0,
-                true,
+                Faction(0),
                 |c| {
                     c.insert(AttackingAI)
                         .insert(AttackingAIState::MoveToNearestEnemy);
@@ -682,9 +1243,11 @@ mod tests {
                 &asset_server,
                 &mut grid,
                 &mut texture_atlases,
+                &archetypes,
+                &ArchetypeId::default(),
                 3,
                 3,
-                false,
+                Faction(1),
                 |c| {
                     c.insert(AttackingAI)
                         .insert(AttackingAIState::MoveToNearestEnemy);
@@ -704,6 +1267,7 @@ mod tests {
 
         App::new()
             .add_plugin(Test::Time(15.0))
+            .add_plugin(SimPlugin)
             .add_plugin(GridPlugin)
             .add_plugin(FxPlugin)
             .add_plugin(UnitPlugin)
@@ -715,6 +1279,84 @@ mod tests {
             .run();
     }
 
+    #[test]
+    #[serial]
+    fn damage_event_removes_its_amount_of_life() {
+        struct Ids(Vec<Entity>);
+
+        fn init(
+            mut commands: Commands,
+            asset_server: Res<AssetServer>,
+            mut grid: ResMut<Grid>,
+            mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
+        ) {
+            let mut ids = Vec::new();
+            for x in 0..2 {
+                spawn_unit(
+                    &mut commands,
+                    &asset_server,
+                    &mut grid,
+                    &mut texture_atlases,
+                    &archetypes,
+                    &ArchetypeId::default(),
+                    x,
+                    0,
+                    Faction(x as u8),
+                    |c| {
+                        c.insert(UnitStats {
+                            life: 5,
+                            damage: 3,
+                            ..Default::default()
+                        });
+                        ids.push(c.id());
+                    },
+                );
+            }
+            commands.insert_resource(Ids(ids));
+        }
+
+        fn attack_once(
+            ids: Res<Ids>,
+            index: Res<UnitIndex>,
+            stats: Query<&UnitStats>,
+            mut events: ResMut<Events<DamageEvent>>,
+            mut done: Local<bool>,
+        ) {
+            if *done || index.units_at(1, 0).is_empty() {
+                return;
+            }
+            assert_eq!(index.units_at(1, 0), &[ids.0[1]]);
+            let attacker = ids.0[0];
+            events.send(DamageEvent::from_stats(
+                attacker,
+                stats.get(attacker).unwrap(),
+                ids.0[1],
+            ));
+            *done = true;
+        }
+
+        fn check_life(mut flag: ResMut<TestCheck<bool>>, query: Query<(&UnitForce, &UnitStats)>) {
+            **flag = query.iter().all(|(force, stats)| {
+                stats.life == if force.faction == Faction(1) { 2 } else { 5 }
+            });
+        }
+
+        App::new()
+            .add_plugin(Test::Time(0.5))
+            .add_plugin(SimPlugin)
+            .add_plugin(GridPlugin)
+            .add_plugin(FxPlugin)
+            .add_plugin(UnitPlugin)
+            .add_system(init_cameras_2d)
+            .insert_resource(Grid::new(2, 1))
+            .insert_resource(TestCheck::new(false).is_true())
+            .add_startup_system(init)
+            .add_system(attack_once)
+            .add_system(check_life)
+            .run();
+    }
+
     #[test]
     #[serial]
     fn dead_unit_are_removed_from_grid() {
@@ -723,15 +1365,18 @@ mod tests {
             asset_server: Res<AssetServer>,
             mut grid: ResMut<Grid>,
             mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+            archetypes: Res<Archetypes>,
         ) {
             spawn_unit(
                 &mut commands,
                 &asset_server,
                 &mut grid,
                 &mut texture_atlases,
+                &archetypes,
+                &ArchetypeId::default(),
                 0,
                 0,
-                true,
+                Faction(0),
                 |c| {
                     c.insert(AttackingAI)
                         .insert(AttackingAIState::MoveToNearestEnemy)
@@ -762,6 +1407,7 @@ mod tests {
 
         App::new()
             .add_plugin(Test::Frames(3))
+            .add_plugin(SimPlugin)
             .add_plugin(GridPlugin)
             .add_plugin(UnitPlugin)
             .add_plugin(FxPlugin)
@@ -8,6 +8,16 @@ use strum_macros::EnumIter;
 #[doc(inline)]
 pub use std;
 
+/// Let optional fields of the data files be written without `Some`, use it with
+/// `#[serde(default, deserialize_with = "crate::utils::deserialize_some")]`.
+pub fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
 #[allow(dead_code)]
 pub fn count_query_filter<Q: WorldQuery, F: WorldQuery>(mut query: Query<Q, F>) {
     let name = std::any::type_name::<Q>();
@@ -0,0 +1,272 @@
+//! Ways to win a match, chosen by the scenario.
+//!
+//! ```ron
+//! victory: [Annihilation, Territory(0.6), Survive(faction: 0, seconds: 180.0), DestroySpawners, Score(500)],
+//! ```
+//!
+//! The conditions are checked in order and the first one met ends the match. Without any, the
+//! match never ends.
+use serde::Deserialize;
+
+use std::fmt;
+
+use crate::faction::*;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub enum VictoryCondition {
+    /// Last side with units or spawners left.
+    Annihilation,
+    /// Hold this part of the walkable cells, between 0 and 1.
+    Territory(f32),
+    /// The side of the faction wins if it still stands after the given seconds.
+    Survive { faction: Faction, seconds: f32 },
+    /// Own every spawner of the map, by destroying or capturing the others.
+    DestroySpawners,
+    /// Reach these points in the territory mode.
+    Score(u32),
+}
+
+impl fmt::Display for VictoryCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VictoryCondition::Annihilation => write!(f, "annihilation"),
+            VictoryCondition::Territory(part) => {
+                write!(f, "holding {:.0}% of the map", part * 100.0)
+            }
+            VictoryCondition::Survive { seconds, .. } => write!(f, "surviving {:.0}s", seconds),
+            VictoryCondition::DestroySpawners => write!(f, "taking every spawner"),
+            VictoryCondition::Score(points) => write!(f, "scoring {} points", points),
+        }
+    }
+}
+
+/// What every faction has at a moment of the battle.
+#[derive(Debug, Default, Clone)]
+pub struct Standing {
+    pub seconds: f32,
+    pub units: [u32; Faction::MAX],
+    pub spawners: [u32; Faction::MAX],
+    /// Cells held by each faction.
+    pub cells: [u32; Faction::MAX],
+    pub walkable_cells: u32,
+    /// Points of each faction in the territory mode.
+    pub score: [u32; Faction::MAX],
+}
+
+impl Standing {
+    /// Sides having something in `counts`, with their total.
+    fn sides(&self, alliances: &Alliances, counts: &[u32; Faction::MAX]) -> Vec<(Side, u32)> {
+        let mut sides: Vec<(Side, u32)> = Vec::new();
+        for faction in Faction::all() {
+            let count = counts[faction.index()];
+            if count == 0 {
+                continue;
+            }
+            let side = alliances.side(faction);
+            match sides.iter_mut().find(|(other, _)| *other == side) {
+                Some((_, total)) => *total += count,
+                None => sides.push((side, count)),
+            }
+        }
+        sides
+    }
+
+    fn standing_sides(&self, alliances: &Alliances) -> Vec<Side> {
+        let mut forces = self.units;
+        for (force, spawners) in forces.iter_mut().zip(self.spawners.iter()) {
+            *force += spawners;
+        }
+        self.sides(alliances, &forces)
+            .into_iter()
+            .map(|(side, _)| side)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchResult {
+    Won {
+        side: Side,
+        condition: VictoryCondition,
+    },
+    /// Every side was wiped out.
+    Draw,
+}
+
+impl fmt::Display for MatchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchResult::Won { side, condition } => {
+                write!(f, "Victory of {} by {}", side, condition)
+            }
+            MatchResult::Draw => write!(f, "Draw, nobody is left"),
+        }
+    }
+}
+
+impl VictoryCondition {
+    /// How the match ends with this condition, if it does now.
+    pub fn check(&self, standing: &Standing, alliances: &Alliances) -> Option<MatchResult> {
+        let won = |side| {
+            Some(MatchResult::Won {
+                side,
+                condition: self.clone(),
+            })
+        };
+        match self {
+            VictoryCondition::Annihilation => match standing.standing_sides(alliances).as_slice() {
+                [] => Some(MatchResult::Draw),
+                [side] => won(*side),
+                _ => None,
+            },
+            VictoryCondition::Territory(part) => {
+                let needed = part * standing.walkable_cells as f32;
+                standing
+                    .sides(alliances, &standing.cells)
+                    .into_iter()
+                    .find(|(_, cells)| *cells as f32 >= needed)
+                    .and_then(|(side, _)| won(side))
+            }
+            VictoryCondition::Survive { faction, seconds } => {
+                let side = alliances.side(*faction);
+                if standing.seconds >= *seconds
+                    && standing.standing_sides(alliances).contains(&side)
+                {
+                    won(side)
+                } else {
+                    None
+                }
+            }
+            VictoryCondition::DestroySpawners => {
+                match standing.sides(alliances, &standing.spawners).as_slice() {
+                    [(side, _)] => won(*side),
+                    _ => None,
+                }
+            }
+            VictoryCondition::Score(points) => standing
+                .sides(alliances, &standing.score)
+                .into_iter()
+                .filter(|(_, score)| score >= points)
+                .max_by_key(|(_, score)| *score)
+                .and_then(|(side, _)| won(side)),
+        }
+    }
+}
+
+/// First condition met, if any.
+pub fn check_victory(
+    conditions: &[VictoryCondition],
+    standing: &Standing,
+    alliances: &Alliances,
+) -> Option<MatchResult> {
+    conditions
+        .iter()
+        .find_map(|condition| condition.check(standing, alliances))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn standing(units: &[u32], spawners: &[u32], cells: &[u32]) -> Standing {
+        let mut standing = Standing {
+            seconds: 10.0,
+            walkable_cells: 10,
+            ..Default::default()
+        };
+        standing.units[..units.len()].copy_from_slice(units);
+        standing.spawners[..spawners.len()].copy_from_slice(spawners);
+        standing.cells[..cells.len()].copy_from_slice(cells);
+        standing
+    }
+
+    fn winner(result: Option<MatchResult>) -> Option<Side> {
+        match result {
+            Some(MatchResult::Won { side, .. }) => Some(side),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn annihilation_counts_spawners_and_allies() {
+        let alliances = Alliances::from_teams(&[0, 0, 1]);
+        let condition = VictoryCondition::Annihilation;
+        assert_eq!(
+            condition.check(&standing(&[2, 0, 0], &[0, 0, 1], &[]), &alliances),
+            None
+        );
+        assert_eq!(
+            winner(condition.check(&standing(&[2, 1, 0], &[], &[]), &alliances)),
+            Some(Side::Team(0))
+        );
+        assert_eq!(
+            condition.check(&standing(&[], &[], &[]), &alliances),
+            Some(MatchResult::Draw)
+        );
+    }
+
+    #[test]
+    fn territory_needs_its_part_of_the_map() {
+        let alliances = Alliances::free_for_all();
+        let condition = VictoryCondition::Territory(0.5);
+        assert_eq!(
+            condition.check(&standing(&[], &[], &[4, 3]), &alliances),
+            None
+        );
+        assert_eq!(
+            winner(condition.check(&standing(&[], &[], &[2, 5]), &alliances)),
+            Some(Side::Alone(Faction(1)))
+        );
+    }
+
+    #[test]
+    fn survivors_win_once_the_time_is_up() {
+        let alliances = Alliances::free_for_all();
+        let condition = VictoryCondition::Survive {
+            faction: Faction(0),
+            seconds: 20.0,
+        };
+        let mut state = standing(&[1, 5], &[], &[]);
+        assert_eq!(condition.check(&state, &alliances), None);
+        state.seconds = 20.0;
+        assert_eq!(
+            winner(condition.check(&state, &alliances)),
+            Some(Side::Alone(Faction(0)))
+        );
+        state.units[0] = 0;
+        assert_eq!(condition.check(&state, &alliances), None);
+    }
+
+    #[test]
+    fn best_score_over_the_target_wins() {
+        let alliances = Alliances::from_teams(&[0, 0, 1]);
+        let mut state = standing(&[], &[], &[]);
+        state.score[..3].copy_from_slice(&[40, 40, 60]);
+        assert_eq!(VictoryCondition::Score(100).check(&state, &alliances), None);
+        assert_eq!(
+            winner(VictoryCondition::Score(50).check(&state, &alliances)),
+            Some(Side::Team(0))
+        );
+    }
+
+    #[test]
+    fn first_condition_met_ends_the_match() {
+        let alliances = Alliances::free_for_all();
+        let conditions = [
+            VictoryCondition::Annihilation,
+            VictoryCondition::DestroySpawners,
+        ];
+        let result = check_victory(&conditions, &standing(&[3, 2], &[0, 1], &[]), &alliances);
+        assert_eq!(
+            result,
+            Some(MatchResult::Won {
+                side: Side::Alone(Faction(1)),
+                condition: VictoryCondition::DestroySpawners,
+            })
+        );
+        assert_eq!(
+            check_victory(&[], &standing(&[], &[], &[]), &alliances),
+            None
+        );
+    }
+}